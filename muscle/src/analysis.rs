@@ -0,0 +1,411 @@
+use serde::{Deserialize, Deserializer};
+use std::path::{Path, PathBuf};
+
+use crate::ffmpeg::OutputFormat;
+
+// Timestamps are strings in any format parse_time reads, or bare JSON numbers of seconds.
+// Numbers are kept as their decimal text, which parse_time always reads as seconds.
+fn string_or_seconds<'de, D: Deserializer<'de>>(deserializer: D) -> Result<String, D::Error> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Timestamp {
+        Text(String),
+        Seconds(f64),
+    }
+    Ok(match Timestamp::deserialize(deserializer)? {
+        Timestamp::Text(text) => text,
+        Timestamp::Seconds(seconds) => seconds.to_string(),
+    })
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct CaptionStyle {
+    // "sans" (default), "serif" or an absolute .ttf/.otf/.ttc path; missing files fall back to sans
+    pub font: Option<String>,
+    // Color name or #RRGGBB / 0xRRGGBB (default white); malformed values fall back with a WARN
+    pub color: Option<String>,
+    // "top", "center", "bottom" (default), or the top edge of the caption as "45%" of the
+    // frame height or pixels ("600" / "600px")
+    pub position: Option<String>,
+    // "left", "center" (default), "right", or the left edge as a percentage or pixels
+    pub x: Option<String>,
+    #[serde(rename = "box")]
+    pub start_box: Option<bool>,
+    pub background_asset: Option<String>,
+    // Wrap captions onto stacked lines of at most this many characters (default 14)
+    pub max_chars_per_line: Option<usize>,
+    // Seconds to ease the caption in/out via drawtext alpha; unset keeps the hard cut
+    pub fade_in: Option<f64>,
+    pub fade_out: Option<f64>,
+    // Color of the active word in karaoke captions (default yellow)
+    pub highlight_color: Option<String>,
+    // Entry motion over the first 0.4s: "slide_up" or "slide_left" (default none)
+    pub animation: Option<String>,
+    // Text outline in pixels (default 2, 0 = none) and its color (default black)
+    pub border_width: Option<u32>,
+    pub border_color: Option<String>,
+}
+
+// A caption shown alongside the cut's main one, placed by its own style
+#[derive(Debug, Clone, Deserialize)]
+pub struct CaptionWithStyle {
+    pub text: String,
+    pub style: Option<CaptionStyle>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct Cut {
+    // Not needed for still cuts, which use duration instead
+    #[serde(default, deserialize_with = "string_or_seconds")]
+    pub start_time: String,
+    #[serde(default, deserialize_with = "string_or_seconds")]
+    pub end_time: String,
+    pub filter: String,
+    // 3D LUT (.cube, relative to NUE_ASSETS_DIR) applied after filter; overrides
+    // the Analysis-wide lut
+    pub lut: Option<String>,
+    pub transition_type: Option<String>,
+    // Overlap in seconds for this cut's incoming transition; overrides the global transition_duration
+    pub transition_duration: Option<f64>,
+    // "left", "right", "up" or "down" for wipe/slide transitions
+    pub transition_direction: Option<String>,
+    pub caption: Option<String>,
+    pub caption_style: Option<CaptionStyle>,
+    pub focus_point: Option<f64>,
+    // [start, end] focus to pan the crop across the cut; overrides focus_point when set
+    pub focus_path: Option<[f64; 2]>,
+    // Karaoke captions: [word, start seconds relative to the cut]; takes precedence over caption
+    pub caption_words: Option<Vec<(String, f64)>>,
+    // More captions drawn at the same time as caption/caption_words, e.g. a top label over
+    // a bottom translation. Layer 0 is the main caption, layer n is captions[n - 1].
+    pub captions: Option<Vec<CaptionWithStyle>>,
+    // Gain for this cut's audio, applied per segment. If any cut sets it, the global
+    // voice boost (audio_boost) moves into the segments and unset cuts get it there instead.
+    pub volume: Option<f64>,
+    // Replace the cut's own audio with silence, leaving only BGM/SE under it (default false)
+    pub mute: Option<bool>,
+    // Seconds to hold the first / last frame (with silence) before / after the cut plays
+    pub freeze_start: Option<f64>,
+    pub freeze_end: Option<f64>,
+    // File this cut comes from instead of original_filename (relative to RAW_DIR):
+    // another video, or an image such as a title card. visual_effects don't apply to these cuts.
+    pub source: Option<String>,
+    // Seconds to show an image source for
+    pub duration: Option<f64>,
+    // Position in the rendered video. Cuts are sorted by it (stably), and cuts without one
+    // come after all ordered cuts in array order.
+    pub order: Option<u32>,
+    // Stretches [from, to) relative to the cut start that trim_silence drops, set at render time
+    #[serde(skip)]
+    pub silences: Vec<(f64, f64)>,
+}
+
+const IMAGE_EXTENSIONS: [&str; 5] = ["png", "jpg", "jpeg", "webp", "bmp"];
+
+impl Cut {
+    // True when the cut shows a still image rather than a stretch of video
+    pub fn is_still(&self) -> bool {
+        self.source.as_deref().is_some_and(|source| {
+            Path::new(source)
+                .extension()
+                .and_then(|ext| ext.to_str())
+                .is_some_and(|ext| IMAGE_EXTENSIONS.contains(&ext.to_lowercase().as_str()))
+        })
+    }
+
+    // Style of caption layer 0 (caption/caption_words) or layer n (captions[n - 1])
+    pub fn layer_style(&self, layer: usize) -> &Option<CaptionStyle> {
+        match layer.checked_sub(1).and_then(|i| self.captions.as_ref()?.get(i)) {
+            Some(extra) => &extra.style,
+            None => &self.caption_style,
+        }
+    }
+
+    // Every caption style the cut may use, main one first
+    pub fn caption_styles(&self) -> impl Iterator<Item = &CaptionStyle> {
+        self.caption_style.iter().chain(self.captions.iter().flatten().filter_map(|c| c.style.as_ref()))
+    }
+
+    // Seconds of the cut dropped as silence
+    pub fn silence_removed(&self) -> f64 {
+        self.silences.iter().map(|(from, to)| to - from).sum()
+    }
+
+    // A time relative to the cut start, moved to the clock of the trimmed cut
+    pub fn trimmed_time(&self, at: f64) -> f64 {
+        at - self.silences.iter().map(|&(from, to)| (at.min(to) - from).max(0.0)).sum::<f64>()
+    }
+
+    // Time the freeze frames add to the cut's rendered length
+    pub fn freeze_duration(&self) -> f64 {
+        self.freeze_start.unwrap_or(0.0) + self.freeze_end.unwrap_or(0.0)
+    }
+}
+
+// One music bed on the final timeline; `bgm_segments` switches music between sections
+#[derive(Debug, Deserialize)]
+pub struct BgmSegment {
+    pub path: String,
+    #[serde(deserialize_with = "string_or_seconds")]
+    pub start: String,
+    #[serde(deserialize_with = "string_or_seconds")]
+    pub end: String,
+    // Fade in/out at the section edges in seconds (default 0.5); 0 is a hard cut
+    pub fade: Option<f64>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct SeEvent {
+    #[serde(deserialize_with = "string_or_seconds")]
+    pub timestamp: String,
+    #[serde(rename = "type")]
+    pub event_type: String, 
+    pub tag: Option<String>,
+    pub volume: Option<f64>,
+    // "left", "right", "center", or a balance between -1.0 (left) and 1.0 (right)
+    pub pan: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct VisualEffect {
+    #[serde(deserialize_with = "string_or_seconds")]
+    pub start: String,
+    #[serde(deserialize_with = "string_or_seconds")]
+    pub end: String,
+    #[serde(rename = "type")]
+    pub effect_type: String,
+    pub speed: Option<String>,
+    // Shake intensity 0..1 (default 0.5)
+    pub amount: Option<f64>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct Thumbnail {
+    #[serde(deserialize_with = "string_or_seconds")]
+    pub timestamp: String,
+    pub text: String,
+    // Like CaptionStyle.color
+    pub color: Option<String>,
+    // "sans" (default), "serif" or an absolute .ttf/.otf/.ttc path, like CaptionStyle.font
+    pub font: Option<String>,
+    // "top", "center" (default) or "bottom"; unlike CaptionStyle.position, no coordinates
+    pub position: Option<String>,
+    // Text size in pixels (default 120)
+    pub fontsize: Option<u32>,
+    // Dark band behind the text for legibility on bright frames: "solid" or "gradient"
+    pub bar: Option<String>,
+    // Also write a short looping "gif" or "webp" preview centered on the timestamp
+    pub preview: Option<String>,
+    // Preview length in seconds (default 2.5)
+    pub preview_duration: Option<f64>,
+    // Skip the static JPEG when a preview is requested
+    pub preview_only: Option<bool>,
+    // Cover image to draw the text on instead of the frame at timestamp (relative to
+    // RAW_DIR); a missing file falls back to the frame
+    pub source_image: Option<String>,
+}
+
+// How three-part timestamps ("a:b:c") are interpreted
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TimeFormat {
+    Hms,      // HH:MM:SS
+    MsMillis, // MM:SS:MMM
+    Seconds,  // plain seconds, e.g. "12.5"
+}
+
+#[derive(Debug, Deserialize)]
+pub struct Analysis {
+    pub cuts: Vec<Cut>,
+    pub original_filename: String,
+    pub bgm_path: Option<String>,
+    // Timed music sections; when set, replaces bgm_path for the whole video
+    pub bgm_segments: Option<Vec<BgmSegment>>,
+    pub se_events: Option<Vec<SeEvent>>,
+    pub visual_effects: Option<Vec<VisualEffect>>,
+    pub thumbnail: Option<Thumbnail>,
+    // Extra covers for A/B testing, written as {filename}_thumb_0.jpg, _thumb_1.jpg, ...
+    pub thumbnails: Option<Vec<Thumbnail>>,
+    // Overlap in seconds used when a cut requests a transition_type (default 0.5)
+    pub transition_duration: Option<f64>,
+    // When absent, parse_time falls back to the legacy >59 heuristic
+    pub time_format: Option<TimeFormat>,
+    // "drawtext" (default, per segment) or "libass": every caption goes into one .ass file
+    // burned in during the final mix (a video re-encode), so Arabic/Hebrew and other complex
+    // scripts are shaped and ordered correctly
+    pub caption_renderer: Option<String>,
+    // Duck the BGM under the video's own audio via sidechaincompress (default off)
+    pub bgm_ducking: Option<bool>,
+    // Dip the BGM briefly around each SE so hits cut through the music (default off)
+    pub se_ducking: Option<bool>,
+    // Gain on the video's own audio when BGM/SE are mixed in (default 1.3, 1.0 = off)
+    pub audio_boost: Option<f64>,
+    // Limit the BGM/SE mix to -1 dBFS so stacked gains can't clip (default on)
+    pub limiter: Option<bool>,
+    // Measure the final mix's true peak after rendering and WARN above 0 dBFS (default off)
+    pub check_clipping: Option<bool>,
+    // Loop a BGM shorter than the video so it runs to the end instead of leaving a silent tail (default off)
+    pub bgm_loop: Option<bool>,
+    // Fade the BGM in over this many seconds at the start (default 0, i.e. no fade)
+    pub bgm_fade_in: Option<f64>,
+    // Fade the final mix out over this many seconds at the end (default 2, 0 disables)
+    pub audio_fade_out: Option<f64>,
+    // Integrated loudness target for the final mix in LUFS, e.g. -14 for social platforms;
+    // unset leaves the mix unnormalized
+    pub target_lufs: Option<f64>,
+    // Also write {output}.srt and {output}.vtt from the cut captions (default off)
+    pub emit_subtitles: Option<bool>,
+    // Add a chapter per cut to the output MP4, titled by its caption (default off)
+    pub emit_chapters: Option<bool>,
+    // Segment encode quality: crf 0-51 (default 23), x264 preset (default "fast"),
+    // AAC bitrate like "192k" (default "128k")
+    pub crf: Option<u8>,
+    pub preset: Option<String>,
+    pub audio_bitrate: Option<String>,
+    // Video bitrate like "2500k" or "4M"; switches segments to two-pass encoding instead of crf
+    pub target_bitrate: Option<String>,
+    // Container and codecs: "mp4/h264", "mp4/hevc" or "webm/vp9" (with Opus audio). Unset
+    // keeps H.264/AAC under original_filename's name; set, the output gets the container's
+    // extension.
+    pub output_format: Option<String>,
+    // POSTed a JSON summary when the render finishes; overrides NUE_WEBHOOK_URL
+    pub webhook_url: Option<String>,
+    // Re-render even if the output is already newer than its inputs (default false)
+    pub force: Option<bool>,
+    // Drop cuts whose segment fails to render and finish with the rest, instead of aborting
+    // the whole video (default false). Skipped indices are listed in the result manifest.
+    pub continue_on_error: Option<bool>,
+    // Cut pauses out of each video cut (default off): audio quieter than silence_threshold dB
+    // (default -35) for at least silence_min_duration seconds (default 0.8) is dropped, minus
+    // a short margin on both sides. Captions and karaoke timings follow the tightened cut.
+    // Cuts with a slowmo/speedup window are left alone.
+    pub trim_silence: Option<bool>,
+    pub silence_threshold: Option<f64>,
+    pub silence_min_duration: Option<f64>,
+    // Cuts shorter than min_cut_duration seconds (default 0.3, 0 disables) flash by as a
+    // broken frame or two. short_cut "pad" (default) extends them to the minimum, "skip"
+    // leaves them out with a WARN.
+    pub min_cut_duration: Option<f64>,
+    pub short_cut: Option<String>,
+    // Join segments by re-encoding instead of a stream copy. Unset, it is chosen automatically
+    // when segments may not match (e.g. slowmo/speedup); false forces the copy.
+    pub concat_reencode: Option<bool>,
+    // Size cap in bytes for the final file; a bigger render is re-encoded at an estimated
+    // lower bitrate until it fits (a few attempts at most, then the render fails)
+    pub max_output_bytes: Option<u64>,
+    // Quick review render: half resolution, ultrafast preset, no BGM/SE, thumbnails skipped,
+    // written as {stem}_preview.mp4 next to the full render (default false)
+    pub preview: Option<bool>,
+    // 3D LUT (.cube, relative to NUE_ASSETS_DIR) for cuts without their own lut
+    pub lut: Option<String>,
+    // Logo image (relative to NUE_ASSETS_DIR) overlaid on every segment
+    pub watermark: Option<String>,
+    // "top_left", "top_right" (default), "bottom_left" or "bottom_right"
+    pub watermark_position: Option<String>,
+    // 0..1, default 0.8
+    pub watermark_opacity: Option<f64>,
+    // Animated audio visualization over the final video: "waves" or "cqt" (default none).
+    // Forces a video re-encode in the final mix.
+    pub waveform: Option<String>,
+    // "top", "center" or "bottom" (default)
+    pub waveform_position: Option<String>,
+    // Band height in pixels (default a sixth of the frame height)
+    pub waveform_height: Option<u32>,
+    // Thin bar that fills across the frame as the video plays (default off); also forces
+    // the final re-encode. Position "top" or "bottom" (default), color like CaptionStyle.color
+    // (default white).
+    pub progress_bar: Option<bool>,
+    pub progress_bar_position: Option<String>,
+    pub progress_bar_color: Option<String>,
+    // File the instruction was read from, set by the caller; used for the up-to-date check
+    #[serde(skip)]
+    pub instruction_path: Option<PathBuf>,
+    // "9:16" (default), "16:9", "1:1" or "4:5"; explicit width/height take precedence
+    pub aspect_ratio: Option<String>,
+    // How sources with a different aspect fill the frame: "crop" (default) or "blur_pad"
+    pub fit_mode: Option<String>,
+    pub width: Option<u32>,
+    pub height: Option<u32>,
+}
+
+pub const DEFAULT_DIMENSIONS: (u32, u32) = (1080, 1920);
+
+// Output frame size for a named aspect ratio
+pub fn dimensions_for_aspect(aspect: &str) -> Option<(u32, u32)> {
+    match aspect.trim() {
+        "9:16" => Some((1080, 1920)),
+        "16:9" => Some((1920, 1080)),
+        "1:1" => Some((1080, 1080)),
+        "4:5" => Some((1080, 1350)),
+        _ => None,
+    }
+}
+
+impl Analysis {
+    // Resolved output width/height; unknown aspect ratios fall back to vertical 1080x1920.
+    // Preview renders use half of it, kept even for yuv420p.
+    pub fn output_dimensions(&self) -> (u32, u32) {
+        let (width, height) = match (self.width, self.height) {
+            (Some(width), Some(height)) => (width, height),
+            _ => self.aspect_ratio.as_deref()
+                .and_then(dimensions_for_aspect)
+                .unwrap_or(DEFAULT_DIMENSIONS),
+        };
+        if self.is_preview() {
+            ((width / 2) & !1, (height / 2) & !1)
+        } else {
+            (width, height)
+        }
+    }
+
+    pub fn uses_libass(&self) -> bool {
+        self.caption_renderer.as_deref() == Some("libass")
+    }
+
+    pub fn is_preview(&self) -> bool {
+        self.preview.unwrap_or(false)
+    }
+
+    // Output codecs; unknown names are rejected by validation
+    pub fn encode_format(&self) -> OutputFormat {
+        self.output_format.as_deref().and_then(OutputFormat::from_name).unwrap_or_default()
+    }
+
+    // Name of the rendered file in the output dir: original_filename, or {stem}_preview.mp4.
+    // An output_format swaps in its container's extension.
+    pub fn output_filename(&self) -> String {
+        if !self.is_preview() && self.output_format.is_none() {
+            return self.original_filename.clone();
+        }
+        let stem = Path::new(&self.original_filename)
+            .file_stem()
+            .map(|s| s.to_string_lossy().into_owned())
+            .unwrap_or_else(|| self.original_filename.clone());
+        let extension = self.encode_format().extension();
+        if self.is_preview() {
+            format!("{}_preview.{}", stem, extension)
+        } else {
+            format!("{}.{}", stem, extension)
+        }
+    }
+
+    // Cuts in render order, each with its index in the `cuts` array
+    pub fn ordered_cuts(&self) -> Vec<(usize, &Cut)> {
+        let mut cuts: Vec<(usize, &Cut)> = self.cuts.iter().enumerate().collect();
+        cuts.sort_by_key(|&(index, cut)| (cut.order.is_none(), cut.order, index));
+        cuts
+    }
+
+    // True when any cut overrides its own audio volume
+    pub fn has_cut_volumes(&self) -> bool {
+        self.cuts.iter().any(|cut| cut.volume.is_some())
+    }
+
+    // All requested thumbnails. A lone legacy `thumbnail` is a one-element list; when
+    // `thumbnails` is also set it is prepended so every cover gets an index.
+    pub fn thumbnail_list(&self) -> Vec<&Thumbnail> {
+        self.thumbnail.iter()
+            .chain(self.thumbnails.iter().flatten())
+            .collect()
+    }
+}
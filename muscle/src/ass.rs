@@ -0,0 +1,208 @@
+// Advanced SubStation Alpha captions for the libass renderer (ffmpeg's `subtitles` filter).
+// The whole video's captions go into one .ass file burned in during the final mix. Unlike
+// drawtext, libass shapes complex scripts and lays out right-to-left text, and picks
+// fallback fonts (e.g. emoji) through fontconfig.
+
+use std::path::Path;
+
+use crate::analysis::{CaptionStyle, Cut};
+use crate::filters::{
+    group_karaoke_lines, resolve_caption_offset, resolve_color, resolve_font, wrap_caption, SANS_FONT, SERIF_FONT,
+};
+use crate::subtitles::Cue;
+
+pub(crate) const CAPTION_RENDERERS: [&str; 2] = ["drawtext", "libass"];
+const DEFAULT_BORDER_WIDTH: u32 = 2;
+// Same timing as the drawtext slide, but \move is linear rather than eased
+const SLIDE_MILLIS: u32 = 400;
+
+// Family names of the bundled Noto CJK Bold files (the style sets Bold); libass looks fonts
+// up by family, not by path
+const SANS_FAMILY: &str = "Noto Sans CJK JP";
+const SERIF_FAMILY: &str = "Noto Serif CJK JP";
+
+// Font family for the style plus the directory holding its file. For a custom file the
+// family is guessed from the file name, which matches most single-family fonts; otherwise
+// fontconfig falls back to its default.
+fn ass_font(style: Option<&CaptionStyle>) -> (String, String) {
+    let path = resolve_font(style.and_then(|s| s.font.as_deref()));
+    let family = match path {
+        SANS_FONT => SANS_FAMILY.to_string(),
+        SERIF_FONT => SERIF_FAMILY.to_string(),
+        custom => Path::new(custom).file_stem().map(|s| s.to_string_lossy().into_owned()).unwrap_or_default(),
+    };
+    let dir = Path::new(path).parent().map(|p| p.to_string_lossy().into_owned()).unwrap_or_default();
+    (family, dir)
+}
+
+// libass takes a single fontsdir: the folder of the first custom font, else the bundled one
+pub(crate) fn fonts_dir(cuts: &[Cut]) -> String {
+    let (_, bundled) = ass_font(None);
+    cuts.iter()
+        .flat_map(|cut| cut.caption_styles())
+        .map(|style| ass_font(Some(style)).1)
+        .find(|dir| *dir != bundled)
+        .unwrap_or(bundled)
+}
+
+// BBGGRR for a drawtext color (name or 0xRRGGBB)
+fn ass_bgr(value: Option<&str>, default: &str) -> String {
+    let resolved = resolve_color(value, default);
+    let hex = match resolved.as_str() {
+        "white" => "FFFFFF",
+        "black" => "000000",
+        "yellow" => "FFFF00",
+        "cyan" => "00FFFF",
+        "red" => "FF0000",
+        "green" => "008000",
+        "blue" => "0000FF",
+        "magenta" => "FF00FF",
+        "orange" => "FFA500",
+        hex => hex.trim_start_matches("0x"),
+    };
+    format!("{}{}{}", &hex[4..6], &hex[2..4], &hex[0..2])
+}
+
+// Literal caption text: braces would open override blocks and a backslash could start an
+// escape like \N, so braces are escaped and backslashes get a word joiner after them
+fn escape_ass(text: &str) -> String {
+    text.replace('\\', "\\\u{2060}").replace('{', "\\{").replace('}', "\\}")
+}
+
+// \an alignment and the anchor point matching the drawtext layout, plus whether the caption
+// must be pinned there with \pos (percentage/pixel coordinates). Unpinned captions are laid
+// out by the style margins, which put them at the same anchor.
+fn placement(style: &Option<CaptionStyle>, (width, height): (u32, u32), font_size: u32) -> (u8, (u32, u32), bool) {
+    let position = style.as_ref().and_then(|s| s.position.as_deref()).unwrap_or("bottom");
+    let x = style.as_ref().and_then(|s| s.x.as_deref()).unwrap_or("center");
+    let custom_x = resolve_caption_offset(x, width);
+    let custom_y = resolve_caption_offset(position, height);
+    let column = match x {
+        "center" => 2,
+        "right" => 3,
+        _ => 1,
+    };
+    let anchor_x = custom_x.unwrap_or(match column {
+        1 => width * 8 / 100,
+        3 => width * 92 / 100,
+        _ => width / 2,
+    });
+    // Top-aligned at a coordinate, or where the drawtext presets put the block
+    let (row, anchor_y) = match (custom_y, position) {
+        (Some(y), _) => (7, y),
+        (None, "top") => (7, height / 10),
+        (None, "center") => (4, height / 2),
+        (None, _) => (1, height * 85 / 100 + font_size),
+    };
+    (row + column - 1, (anchor_x, anchor_y), custom_x.is_some() || custom_y.is_some())
+}
+
+fn style_line(name: &str, style: &Option<CaptionStyle>, dimensions: (u32, u32), font_size: u32) -> String {
+    let (width, height) = dimensions;
+    let (family, _) = ass_font(style.as_ref());
+    let primary = ass_bgr(style.as_ref().and_then(|s| s.color.as_deref()), "white");
+    // box=true: opaque box (BorderStyle 3) in translucent black, like drawtext's boxcolor=black@0.5
+    let (border_style, outline, border) = if style.as_ref().and_then(|s| s.start_box).unwrap_or(false) {
+        (3, "80000000".to_string(), 5)
+    } else {
+        let color = ass_bgr(style.as_ref().and_then(|s| s.border_color.as_deref()), "black");
+        (1, format!("00{}", color), style.as_ref().and_then(|s| s.border_width).unwrap_or(DEFAULT_BORDER_WIDTH))
+    };
+    let (alignment, _, _) = placement(style, dimensions, font_size);
+    let margin_h = width * 8 / 100;
+    let margin_v = match alignment {
+        7..=9 => height / 10,
+        4..=6 => 0,
+        _ => (height * 15 / 100).saturating_sub(font_size),
+    };
+    format!(
+        "Style: {name},{family},{font_size},&H00{primary},&H00{primary},&H{outline},&H80000000,-1,0,0,0,100,100,0,0,\
+         {border_style},{border},0,{alignment},{margin_h},{margin_h},{margin_v},1\n"
+    )
+}
+
+// Override tags for a whole event: placement/motion and fades
+fn event_tags(style: &Option<CaptionStyle>, dimensions: (u32, u32), font_size: u32) -> String {
+    let (width, height) = dimensions;
+    let (alignment, (x, y), pinned) = placement(style, dimensions, font_size);
+    let mut tags = String::new();
+    match style.as_ref().and_then(|s| s.animation.as_deref()) {
+        Some("slide_up") => tags.push_str(&format!("\\an{}\\move({},{},{},{},0,{})", alignment, x, y + height, x, y, SLIDE_MILLIS)),
+        Some("slide_left") => tags.push_str(&format!("\\an{}\\move({},{},{},{},0,{})", alignment, x + width, y, x, y, SLIDE_MILLIS)),
+        _ if pinned => tags.push_str(&format!("\\an{}\\pos({},{})", alignment, x, y)),
+        _ => {}
+    }
+    let fade_in = style.as_ref().and_then(|s| s.fade_in).unwrap_or(0.0);
+    let fade_out = style.as_ref().and_then(|s| s.fade_out).unwrap_or(0.0);
+    if fade_in > 0.0 || fade_out > 0.0 {
+        tags.push_str(&format!("\\fad({},{})", (fade_in * 1000.0).round() as u64, (fade_out * 1000.0).round() as u64));
+    }
+    tags
+}
+
+// Karaoke reveal like the drawtext renderer: each word stays hidden until its time, shows in
+// the highlight color while active, then settles to the base color. Times are relative to
+// the event start.
+fn karaoke_text(words: &[(String, f64)], style: &Option<CaptionStyle>, max_chars: usize, end: f64) -> String {
+    let base = ass_bgr(style.as_ref().and_then(|s| s.color.as_deref()), "white");
+    let highlight = ass_bgr(style.as_ref().and_then(|s| s.highlight_color.as_deref()), "yellow");
+    let millis = |seconds: f64| (seconds.max(0.0) * 1000.0).round() as u64;
+    let lines: Vec<String> = group_karaoke_lines(words, max_chars).iter().map(|indices| {
+        indices.iter().map(|&i| {
+            let from = millis(words[i].1);
+            let to = millis(words.get(i + 1).map(|(_, t)| *t).unwrap_or(end));
+            format!(
+                "{{\\alpha&HFF&\\1c&H{highlight}&\\t({from},{from},\\alpha&H00&)\\t({to},{to},\\1c&H{base}&)}}{}",
+                escape_ass(&words[i].0)
+            )
+        }).collect::<Vec<_>>().join(" ")
+    }).collect();
+    lines.join("\\N")
+}
+
+// A complete .ass document for the cues, each styled by the cut it came from, on a canvas
+// the size of the output so pixel values match the drawtext renderer
+pub(crate) fn to_ass(cues: &[Cue], cuts: &[Cut], dimensions: (u32, u32), font_size: u32, default_max_chars: usize) -> String {
+    let (width, height) = dimensions;
+    let mut out = format!(
+        "[Script Info]\nScriptType: v4.00+\nPlayResX: {width}\nPlayResY: {height}\nWrapStyle: 2\nScaledBorderAndShadow: yes\n\n\
+         [V4+ Styles]\n\
+         Format: Name, Fontname, Fontsize, PrimaryColour, SecondaryColour, OutlineColour, BackColour, Bold, Italic, Underline, StrikeOut, \
+         ScaleX, ScaleY, Spacing, Angle, BorderStyle, Outline, Shadow, Alignment, MarginL, MarginR, MarginV, Encoding\n"
+    );
+    for cue in cues {
+        out.push_str(&style_line(&style_name(cue), cuts[cue.cut].layer_style(cue.layer), dimensions, font_size));
+    }
+
+    out.push_str("\n[Events]\nFormat: Layer, Start, End, Style, Name, MarginL, MarginR, MarginV, Effect, Text\n");
+    for cue in cues {
+        let cut = &cuts[cue.cut];
+        let style = cut.layer_style(cue.layer);
+        let max_chars = style.as_ref().and_then(|s| s.max_chars_per_line).unwrap_or(default_max_chars);
+        let text = match cut.caption_words.as_deref().filter(|w| cue.layer == 0 && !w.is_empty()) {
+            Some(words) => karaoke_text(words, style, max_chars, cue.end - cue.start),
+            None => wrap_caption(&cue.text, max_chars).iter().map(|line| escape_ass(line)).collect::<Vec<_>>().join("\\N"),
+        };
+        let tags = event_tags(style, dimensions, font_size);
+        let tags = if tags.is_empty() { tags } else { format!("{{{}}}", tags) };
+        out.push_str(&format!(
+            "Dialogue: 0,{},{},{},,0,0,0,,{}{}\n",
+            ass_timestamp(cue.start), ass_timestamp(cue.end), style_name(cue), tags, text
+        ));
+    }
+    out
+}
+
+// Cut{n} for a cut's main caption, Cut{n}_{layer} for its extra ones
+fn style_name(cue: &Cue) -> String {
+    match cue.layer {
+        0 => format!("Cut{}", cue.cut),
+        layer => format!("Cut{}_{}", cue.cut, layer),
+    }
+}
+
+// H:MM:SS.cc (centiseconds)
+fn ass_timestamp(seconds: f64) -> String {
+    let centis = (seconds.max(0.0) * 100.0).round() as u64;
+    format!("{}:{:02}:{:02}.{:02}", centis / 360_000, centis / 6000 % 60, centis / 100 % 60, centis % 100)
+}
@@ -0,0 +1,823 @@
+// ffmpeg argv builders. The render code resolves files, logs and decides; these only turn
+// those decisions into arguments, so a command can be checked without spawning ffmpeg.
+
+use std::os::unix::process::CommandExt;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+
+use crate::ffmpeg::{EncodeSettings, VideoEncoder, PROGRESS_ARGS};
+use crate::filters::{get_atempo_chain, get_limiter_filter, get_se_duck_filter};
+
+// Generated input for segments whose source has no audio
+const SILENT_AUDIO: &str = "anullsrc=channel_layout=stereo:sample_rate=48000";
+// BGM sits well under the voice
+const BGM_VOLUME: f64 = 0.08;
+
+// ffmpeg gets its own process group, so a Ctrl-C in the terminal reaches only us and
+// shutdown decides whether to wait for it (see ffmpeg::kill_running). Stdin is closed:
+// a background group reading the terminal would be stopped.
+pub(crate) fn ffmpeg_command(args: Vec<String>) -> Command {
+    let mut cmd = Command::new("ffmpeg");
+    cmd.args(args).stdin(Stdio::null()).process_group(0);
+    cmd
+}
+
+fn push(args: &mut Vec<String>, items: &[&str]) {
+    args.extend(items.iter().map(|item| item.to_string()));
+}
+
+fn path_arg(path: &Path) -> String {
+    path.to_string_lossy().into_owned()
+}
+
+// One frame from the video at `timestamp`, or the cover image when there is one
+pub(crate) fn build_thumbnail_args(video: &Path, timestamp: &str, cover: Option<&Path>, filter: &str, output: &Path) -> Vec<String> {
+    let mut args = vec!["-y".to_string()];
+    match cover {
+        Some(cover) => push(&mut args, &["-i", &path_arg(cover)]),
+        None => push(&mut args, &["-ss", timestamp, "-i", &path_arg(video)]),
+    }
+    push(&mut args, &["-vf", filter, "-vframes", "1", &path_arg(output)]);
+    args
+}
+
+// Looping GIF/WebP clip of `duration` seconds from `start`
+pub(crate) fn build_preview_args(video: &Path, start: f64, duration: f64, format: &str, filter: &str, output: &Path) -> Vec<String> {
+    let mut args = vec!["-y".to_string()];
+    push(&mut args, &[
+        "-ss", &format!("{:.3}", start),
+        "-t", &format!("{:.3}", duration),
+        "-i", &path_arg(video),
+        "-vf", filter,
+        "-an", "-loop", "0",
+    ]);
+    if format == "webp" {
+        push(&mut args, &["-c:v", "libwebp", "-quality", "70"]);
+    }
+    push(&mut args, &[&path_arg(output)]);
+    args
+}
+
+// Everything a segment encode needs once process_single_segment has built its filters
+pub(crate) struct SegmentCommand<'a> {
+    pub input: &'a Path,
+    // Where the cut starts in the source; ignored for stills
+    pub start: f64,
+    // Frame rate a still image loops at, None for video
+    pub still_fps: Option<f64>,
+    // Without it, generated silence stands in for the source audio
+    pub has_audio: bool,
+    // Source span the filters run over, and the output length including freeze frames
+    pub duration: f64,
+    pub rendered: f64,
+    pub video_filter: &'a str,
+    // Silence-trim audio filter, paired with the video one already in video_filter
+    pub audio_trim: Option<&'a str>,
+    pub audio_volume: Option<f64>,
+    // Freeze-frame (video, audio) filters
+    pub hold: Option<&'a (String, String)>,
+    // Speed window (from, to, factor)
+    pub speed: Option<(f64, f64, f64)>,
+    pub encoder: VideoEncoder,
+    pub settings: &'a EncodeSettings,
+}
+
+// Two-pass encodes run the same command twice; the first only writes the stats log
+pub(crate) enum Pass<'a> {
+    Single,
+    First(&'a Path),
+    Second(&'a Path),
+}
+
+pub(crate) fn build_segment_args(segment: &SegmentCommand, pass: Pass, output: &Path) -> Vec<String> {
+    let mut args = vec!["-y".to_string()];
+    push(&mut args, &PROGRESS_ARGS);
+    push(&mut args, &segment.encoder.input_args());
+    match segment.still_fps {
+        // A still loops its single frame at the source's rate; -t below ends it
+        Some(fps) => push(&mut args, &["-loop", "1", "-framerate", &format!("{:.3}", fps)]),
+        // Seek before -i for fast, accurate seeking
+        None => push(&mut args, &["-ss", &format!("{:.3}", segment.start)]),
+    }
+    push(&mut args, &["-i", &path_arg(segment.input)]);
+    // No audio in the source: input 1 is generated silence, cut to length by -t
+    if !segment.has_audio {
+        push(&mut args, &["-f", "lavfi", "-i", SILENT_AUDIO]);
+    }
+    push(&mut args, &["-t", &format!("{:.3}", segment.rendered)]);
+
+    let audio_input = if segment.has_audio { "0:a" } else { "1:a" };
+    let upload = segment.encoder.upload_filter();
+    if let Some(window) = segment.speed {
+        let graph = get_speed_graph(segment.video_filter, audio_input, upload, segment.audio_volume, segment.hold, segment.duration, window);
+        push(&mut args, &["-filter_complex", &graph, "-map", "[vout]", "-map", "[aout]"]);
+    } else {
+        let video_chain: Vec<&str> = [Some(segment.video_filter), segment.hold.map(|(v, _)| v.as_str()), upload]
+            .into_iter()
+            .flatten()
+            .collect();
+        push(&mut args, &["-vf", &video_chain.join(",")]);
+        if !segment.has_audio {
+            push(&mut args, &["-map", "0:v", "-map", audio_input]);
+        }
+        let audio_chain: Vec<String> = [
+            segment.audio_trim.map(String::from),
+            segment.audio_volume.map(|v| format!("volume={}", v)),
+            segment.hold.map(|(_, a)| a.clone()),
+        ]
+        .into_iter()
+        .flatten()
+        .collect();
+        if !audio_chain.is_empty() {
+            push(&mut args, &["-af", &audio_chain.join(",")]);
+        }
+    }
+    args.extend(segment.encoder.output_args(segment.settings));
+    push(&mut args, &["-c:a", "aac", "-b:a", &segment.settings.audio_bitrate]);
+
+    match pass {
+        Pass::Single => push(&mut args, &[&path_arg(output)]),
+        Pass::First(log) => push(&mut args, &["-pass", "1", "-passlogfile", &path_arg(log), "-f", "null", "-"]),
+        Pass::Second(log) => push(&mut args, &["-pass", "2", "-passlogfile", &path_arg(log), &path_arg(output)]),
+    }
+    args
+}
+
+// Split the segment into before/during/after the speed window, retime the middle piece
+// and join them back. Empty outer pieces are dropped. Freeze frames (`hold`) go after the join.
+fn get_speed_graph(
+    video_filter: &str,
+    audio_input: &str,
+    upload: Option<&str>,
+    audio_volume: Option<f64>,
+    hold: Option<&(String, String)>,
+    duration: f64,
+    window: (f64, f64, f64),
+) -> String {
+    let (from, to, factor) = window;
+    let pieces: Vec<(f64, f64, bool)> = [(0.0, from, false), (from, to, true), (to, duration, false)]
+        .into_iter()
+        .filter(|(a, b, _)| b - a > 0.0005)
+        .collect();
+    let n = pieces.len();
+    let volume = audio_volume.map(|v| format!("volume={},", v)).unwrap_or_default();
+
+    let mut parts = vec![
+        format!("[0:v]{},split={}{}", video_filter, n, (0..n).map(|i| format!("[vs{}]", i)).collect::<String>()),
+        format!("[{}]{}asplit={}{}", audio_input, volume, n, (0..n).map(|i| format!("[as{}]", i)).collect::<String>()),
+    ];
+    let mut concat_inputs = String::new();
+    for (i, (a, b, retimed)) in pieces.iter().enumerate() {
+        let (setpts, atempo) = if *retimed {
+            (format!("(PTS-STARTPTS)/{}", factor), format!(",{}", get_atempo_chain(factor)))
+        } else {
+            ("PTS-STARTPTS".to_string(), String::new())
+        };
+        parts.push(format!("[vs{i}]trim={a:.3}:{b:.3},setpts={setpts}[vp{i}]"));
+        parts.push(format!("[as{i}]atrim={a:.3}:{b:.3},asetpts=PTS-STARTPTS{atempo}[ap{i}]"));
+        concat_inputs.push_str(&format!("[vp{i}][ap{i}]"));
+    }
+    let video_tail: Vec<&str> = [hold.map(|(v, _)| v.as_str()), upload].into_iter().flatten().collect();
+    let audio_tail = hold.map(|(_, a)| a.as_str());
+    let video_label = if video_tail.is_empty() { "[vout]" } else { "[vjoined]" };
+    let audio_label = if audio_tail.is_none() { "[aout]" } else { "[ajoined]" };
+    parts.push(format!("{}concat=n={}:v=1:a=1{}{}", concat_inputs, n, video_label, audio_label));
+    if !video_tail.is_empty() {
+        parts.push(format!("[vjoined]{}[vout]", video_tail.join(",")));
+    }
+    if let Some(tail) = audio_tail {
+        parts.push(format!("[ajoined]{}[aout]", tail));
+    }
+    parts.join(";")
+}
+
+// An xfade/acrossfade into a segment, timed by stitch_with_transitions
+pub(crate) struct Xfade {
+    pub transition: String,
+    pub duration: f64,
+    // Where the transition starts on the joined timeline
+    pub offset: f64,
+}
+
+// Segments joined in one graph. joins[i] is how segment i + 1 meets the joined video so far:
+// an xfade, or a hard concat when None.
+pub(crate) struct Stitch<'a> {
+    pub segments: &'a [PathBuf],
+    pub joins: &'a [Option<Xfade>],
+    pub encoder: VideoEncoder,
+    pub settings: &'a EncodeSettings,
+    pub output: &'a Path,
+}
+
+// The -filter_complex joining the segments into [vout]/[aout]
+pub(crate) fn stitch_filter(stitch: &Stitch) -> String {
+    let mut filter_parts = Vec::new();
+    let mut prev_v = "[0:v]".to_string();
+    let mut prev_a = "[0:a]".to_string();
+
+    for (i, join) in stitch.joins.iter().enumerate() {
+        let input = i + 1;
+        let (out_v, out_a) = if input == stitch.joins.len() {
+            ("[vout]".to_string(), "[aout]".to_string())
+        } else {
+            (format!("[v{}]", input), format!("[a{}]", input))
+        };
+        match join {
+            Some(xfade) => {
+                filter_parts.push(format!("{}[{}:v]xfade=transition={}:duration={:.3}:offset={:.3}{}",
+                    prev_v, input, xfade.transition, xfade.duration, xfade.offset, out_v));
+                filter_parts.push(format!("{}[{}:a]acrossfade=d={:.3}{}", prev_a, input, xfade.duration, out_a));
+            }
+            None => {
+                filter_parts.push(format!("{}[{}:v]concat=n=2:v=1:a=0{}", prev_v, input, out_v));
+                filter_parts.push(format!("{}[{}:a]concat=n=2:v=0:a=1{}", prev_a, input, out_a));
+            }
+        }
+        prev_v = out_v;
+        prev_a = out_a;
+    }
+
+    // Hardware encoders that need an upload step get it after the last video join
+    if let Some(upload) = stitch.encoder.upload_filter() {
+        if let Some(last) = filter_parts.iter_mut().rev().find(|part| part.ends_with("[vout]")) {
+            last.truncate(last.len() - "[vout]".len());
+            last.push_str("[vjoined]");
+        }
+        filter_parts.push(format!("[vjoined]{}[vout]", upload));
+    }
+    filter_parts.join(";")
+}
+
+pub(crate) fn build_stitch_args(stitch: &Stitch) -> Vec<String> {
+    let mut args = vec!["-y".to_string()];
+    push(&mut args, &PROGRESS_ARGS);
+    push(&mut args, &stitch.encoder.input_args());
+    for segment in stitch.segments {
+        push(&mut args, &["-i", &path_arg(segment)]);
+    }
+    push(&mut args, &["-filter_complex", &stitch_filter(stitch), "-map", "[vout]", "-map", "[aout]"]);
+    args.extend(stitch.encoder.output_args(stitch.settings));
+    push(&mut args, &["-c:a", "aac", "-b:a", &stitch.settings.audio_bitrate, &path_arg(stitch.output)]);
+    args
+}
+
+// A bgm_segments entry resolved to seconds, with its file known to exist
+pub(crate) struct BgmSection {
+    pub path: PathBuf,
+    pub start: f64,
+    pub end: f64,
+    pub fade: f64,
+}
+
+fn get_bgm_section_filter(section: &BgmSection) -> String {
+    let length = section.end - section.start;
+    let fade = section.fade.min(length / 2.0);
+    let fades = if fade > 0.0 {
+        format!(",afade=t=in:st=0:d={:.3},afade=t=out:st={:.3}:d={:.3}", fade, length - fade, fade)
+    } else {
+        String::new()
+    };
+    let delay_ms = (section.start * 1000.0).round() as u64;
+    format!(
+        "atrim=duration={:.3},asetpts=PTS-STARTPTS,volume={}{},adelay={}:all=1",
+        length, BGM_VOLUME, fades, delay_ms
+    )
+}
+
+pub(crate) enum Bgm<'a> {
+    // One bed under the whole video, optionally faded in
+    Track { path: &'a Path, fade_in: Option<f64> },
+    // Timed sections from bgm_segments
+    Sections(&'a [BgmSection]),
+}
+
+pub(crate) struct SeInput<'a> {
+    pub path: &'a Path,
+    // Seconds into the final timeline
+    pub at: f64,
+    pub volume: f64,
+    // Resolved pan filter, if any
+    pub pan: Option<String>,
+}
+
+// The voice/BGM/SE mix. The voice is input 0's audio; BGM and SE files follow the other inputs.
+pub(crate) struct AudioMix<'a> {
+    pub voice_volume: f64,
+    pub bgm: Option<Bgm<'a>>,
+    pub bgm_loop: bool,
+    // Sidechain the BGM under the voice
+    pub ducking: bool,
+    // Dip the BGM under each SE
+    pub se_ducking: bool,
+    pub se: Vec<SeInput<'a>>,
+    // Fade-out over the end of the mix, already capped at its length (0 = none)
+    pub fade_out: f64,
+    pub limiter: bool,
+}
+
+// Where the joined video comes from: the stitched file, or a concat demuxer list of segments
+pub(crate) enum JoinedVideo<'a> {
+    Stitched(&'a Path),
+    ConcatList(&'a Path),
+}
+
+// The final mix: joined video, optional chapters, then the audio mix inputs
+pub(crate) struct FinalMix<'a> {
+    pub joined: JoinedVideo<'a>,
+    pub chapters: Option<&'a Path>,
+    // None passes the joined audio through, loudness-normalized when loudnorm is set
+    pub audio: Option<AudioMix<'a>>,
+    // Set when the Analysis asks for a target_lufs
+    pub loudnorm: Option<&'a str>,
+    // Graph tail drawing on [0:v] (see final_video_graph); the video is re-encoded when set
+    pub video_graph: Option<&'a str>,
+    // Where the mix ends: [mixed] when the video graph splits it off again, else [aout]
+    pub mix_label: &'a str,
+    pub encoder: VideoEncoder,
+    pub settings: &'a EncodeSettings,
+    pub duration: f64,
+    pub output: &'a Path,
+}
+
+impl FinalMix<'_> {
+    // Chapters take input 1 when present, ahead of the audio inputs
+    fn chapters_input(&self) -> Option<usize> {
+        self.chapters.map(|_| 1)
+    }
+}
+
+// The -filter_complex for the final mix, or None when a plain -af does
+pub(crate) fn final_mix_filter(mix: &FinalMix) -> Option<String> {
+    let video_graph = mix.video_graph.unwrap_or("");
+    match &mix.audio {
+        Some(audio) => {
+            let first_input = mix.chapters_input().map_or(1, |i| i + 1);
+            Some(format!("{}{}", audio_mix_graph(audio, first_input, mix), video_graph))
+        }
+        None if mix.video_graph.is_some() => Some(format!("[0:a]{}{}{}", mix.loudnorm.unwrap_or("anull"), mix.mix_label, video_graph)),
+        None => None,
+    }
+}
+
+fn audio_mix_graph(audio: &AudioMix, first_input: usize, mix: &FinalMix) -> String {
+    let mut input_index = first_input;
+    let mut filter_parts = Vec::new();
+    let mut input_labels = vec!["[v_in]".to_string()];
+
+    // With ducking, the boosted voice is also split off as the sidechain key for the BGM
+    if audio.ducking {
+        filter_parts.push(format!("[0:a]volume={},asplit=2[v_in][duck_key]", audio.voice_volume));
+    } else {
+        filter_parts.push(format!("[0:a]volume={}[v_in]", audio.voice_volume));
+    }
+
+    if let Some(bgm) = &audio.bgm {
+        let bgm_out = if audio.se_ducking { "[bgm_full]" } else { "[bgm]" };
+        let bgm_label = if audio.ducking { "[bgm_raw]" } else { bgm_out };
+        match bgm {
+            // Each section: trimmed to its length, faded at the edges, delayed to its start.
+            // normalize=0 keeps amix from scaling the (mostly non-overlapping) sections down.
+            Bgm::Sections(sections) => {
+                let mut section_labels = String::new();
+                for (k, section) in sections.iter().enumerate() {
+                    filter_parts.push(format!("[{}:a]{}[bgm_s{}]", input_index, get_bgm_section_filter(section), k));
+                    section_labels.push_str(&format!("[bgm_s{}]", k));
+                    input_index += 1;
+                }
+                filter_parts.push(format!("{}amix=inputs={}:duration=longest:normalize=0{}", section_labels, sections.len(), bgm_label));
+            }
+            Bgm::Track { fade_in, .. } => {
+                let fade_in = match fade_in {
+                    Some(seconds) if *seconds > 0.0 => format!(",afade=t=in:st=0:d={:.3}", seconds),
+                    _ => String::new(),
+                };
+                filter_parts.push(format!("[{}:a]volume={}{}{}", input_index, BGM_VOLUME, fade_in, bgm_label));
+                input_index += 1;
+            }
+        }
+        if audio.ducking {
+            filter_parts.push(format!("[bgm_raw][duck_key]sidechaincompress=threshold=0.02:ratio=8:attack=20:release=400{}", bgm_out));
+        }
+        if audio.se_ducking {
+            let times: Vec<f64> = audio.se.iter().map(|se| se.at).collect();
+            filter_parts.push(format!("[bgm_full]{}[bgm]", get_se_duck_filter(&times)));
+        }
+        input_labels.push("[bgm]".to_string());
+    }
+
+    // Each SE delayed to its timestamp on both channels
+    for se in &audio.se {
+        let delay_ms = (se.at * 1000.0) as i64;
+        let pan = se.pan.as_ref().map(|pan| format!(",{}", pan)).unwrap_or_default();
+        filter_parts.push(format!("[{}:a]adelay={}|{},volume={}{}[se{}]", input_index, delay_ms, delay_ms, se.volume, pan, input_index));
+        input_labels.push(format!("[se{}]", input_index));
+        input_index += 1;
+    }
+
+    // inputs=N counts the voice plus BGM and SEs. loudnorm goes before the fade, since its
+    // dynamic gain would otherwise bring the fading tail back up.
+    let mut chain = vec![format!("amix=inputs={}:duration=first", input_labels.len())];
+    chain.extend(mix.loudnorm.map(String::from));
+    // Clips shorter than the fade fade over their whole length
+    if audio.fade_out > 0.0 {
+        chain.push(format!("afade=t=out:st={:.3}:d={:.3}", mix.duration - audio.fade_out, audio.fade_out));
+    }
+    // Last in the chain, so nothing after it can push the mix back over the ceiling
+    if audio.limiter {
+        chain.push(get_limiter_filter().to_string());
+    }
+
+    format!("{};{}{}{}", filter_parts.join(";"), input_labels.join(""), chain.join(","), mix.mix_label)
+}
+
+pub(crate) fn build_concat_args(mix: &FinalMix) -> Vec<String> {
+    let mut args = vec!["-y".to_string()];
+    push(&mut args, &PROGRESS_ARGS);
+    if mix.video_graph.is_some() {
+        push(&mut args, &mix.encoder.input_args());
+    }
+    match mix.joined {
+        JoinedVideo::Stitched(path) => push(&mut args, &["-i", &path_arg(path)]),
+        JoinedVideo::ConcatList(list) => push(&mut args, &["-f", "concat", "-safe", "0", "-i", &path_arg(list)]),
+    }
+    if let Some(chapters) = mix.chapters {
+        push(&mut args, &["-i", &path_arg(chapters)]);
+    }
+
+    if let Some(audio) = &mix.audio {
+        // A looped input never ends on its own; amix duration=first and -t stop it with the video
+        let mut bgm_input = |path: &Path| {
+            if audio.bgm_loop {
+                push(&mut args, &["-stream_loop", "-1"]);
+            }
+            push(&mut args, &["-i", &path_arg(path)]);
+        };
+        match &audio.bgm {
+            Some(Bgm::Track { path, .. }) => bgm_input(path),
+            Some(Bgm::Sections(sections)) => sections.iter().for_each(|section| bgm_input(&section.path)),
+            None => {}
+        }
+        for se in &audio.se {
+            push(&mut args, &["-i", &path_arg(se.path)]);
+        }
+    }
+
+    match final_mix_filter(mix) {
+        Some(filter) => {
+            let video = if mix.video_graph.is_some() { "[vout]" } else { "0:v" };
+            push(&mut args, &["-filter_complex", &filter, "-map", video, "-map", "[aout]"]);
+        }
+        None => {
+            if let Some(loudnorm) = mix.loudnorm {
+                push(&mut args, &["-af", loudnorm]);
+            }
+        }
+    }
+
+    // Without a video graph the joined video is already encoded and only copied
+    if mix.video_graph.is_some() {
+        args.extend(mix.encoder.output_args(mix.settings));
+    } else {
+        push(&mut args, &["-c:v", "copy"]);
+    }
+    if let Some(input) = mix.chapters_input() {
+        let input = input.to_string();
+        push(&mut args, &["-map_metadata", &input, "-map_chapters", &input]);
+    }
+    // -t holds the output to the video's length
+    push(&mut args, &[
+        "-c:a", mix.settings.format.audio_codec(),
+        "-t", &format!("{:.3}", mix.duration),
+        &path_arg(mix.output),
+    ]);
+    args
+}
+
+// Re-encode the rendered output's video at settings.target_bitrate for max_output_bytes;
+// the audio is copied
+pub(crate) fn build_resize_args(input: &Path, encoder: VideoEncoder, settings: &EncodeSettings, output: &Path) -> Vec<String> {
+    let mut args = vec!["-y".to_string()];
+    push(&mut args, &PROGRESS_ARGS);
+    push(&mut args, &encoder.input_args());
+    push(&mut args, &["-i", &path_arg(input)]);
+    if let Some(upload) = encoder.upload_filter() {
+        push(&mut args, &["-vf", upload]);
+    }
+    args.extend(encoder.output_args(settings));
+    push(&mut args, &["-c:a", "copy", &path_arg(output)]);
+    args
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::analysis::Analysis;
+    use crate::filters::get_loudnorm_filter;
+    use std::sync::LazyLock;
+
+    // The filter a target_lufs of -14 (the usual social platform target) ships
+    static LOUDNORM: LazyLock<String> = LazyLock::new(|| get_loudnorm_filter(-14.0));
+
+    fn settings(extra: serde_json::Value) -> EncodeSettings {
+        let mut json = serde_json::json!({ "original_filename": "in.mp4", "cuts": [] });
+        json.as_object_mut().unwrap().extend(extra.as_object().unwrap().clone());
+        let analysis: Analysis = serde_json::from_value(json).unwrap();
+        EncodeSettings::from_analysis(&analysis)
+    }
+
+    fn segment<'a>(settings: &'a EncodeSettings, video_filter: &'a str) -> SegmentCommand<'a> {
+        SegmentCommand {
+            input: Path::new("/raw/in.mp4"),
+            start: 1.0,
+            still_fps: None,
+            has_audio: true,
+            duration: 3.0,
+            rendered: 3.0,
+            video_filter,
+            audio_trim: None,
+            audio_volume: None,
+            hold: None,
+            speed: None,
+            encoder: VideoEncoder::Libx264,
+            settings,
+        }
+    }
+
+    fn final_mix<'a>(settings: &'a EncodeSettings) -> FinalMix<'a> {
+        FinalMix {
+            joined: JoinedVideo::ConcatList(Path::new("/temp/list.txt")),
+            chapters: None,
+            audio: None,
+            loudnorm: Some(&LOUDNORM),
+            video_graph: None,
+            mix_label: "[aout]",
+            encoder: VideoEncoder::Libx264,
+            settings,
+            duration: 6.0,
+            output: Path::new("/out/in.mp4"),
+        }
+    }
+
+    fn audio_mix<'a>(bgm: Option<Bgm<'a>>, se: Vec<SeInput<'a>>) -> AudioMix<'a> {
+        AudioMix { voice_volume: 1.3, bgm, bgm_loop: false, ducking: false, se_ducking: false, se, fade_out: 2.0, limiter: true }
+    }
+
+    fn se(path: &str, at: f64) -> SeInput<'_> {
+        SeInput { path: Path::new(path), at, volume: 0.8, pan: None }
+    }
+
+    // Loudness, fade-out and limiter closing every mix of the 6s test render
+    fn mix_tail() -> String {
+        format!("{},afade=t=out:st=4.000:d=2.000,alimiter=limit=0.891:attack=5:release=50:level=0[aout]", *LOUDNORM)
+    }
+
+    fn argv(args: &[&str]) -> Vec<String> {
+        args.iter().map(|arg| arg.to_string()).collect()
+    }
+
+    #[test]
+    fn thumbnail_seeks_the_video_or_reads_the_cover() {
+        let (video, out) = (Path::new("/raw/in.mp4"), Path::new("/out/thumb.jpg"));
+        assert_eq!(
+            build_thumbnail_args(video, "00:00:02", None, "scale=1080:1920", out),
+            argv(&["-y", "-ss", "00:00:02", "-i", "/raw/in.mp4", "-vf", "scale=1080:1920", "-vframes", "1", "/out/thumb.jpg"])
+        );
+        assert_eq!(
+            build_thumbnail_args(video, "00:00:02", Some(Path::new("/raw/cover.png")), "scale=1080:1920", out),
+            argv(&["-y", "-i", "/raw/cover.png", "-vf", "scale=1080:1920", "-vframes", "1", "/out/thumb.jpg"])
+        );
+    }
+
+    #[test]
+    fn preview_loops_and_encodes_webp_with_libwebp() {
+        let video = Path::new("/raw/in.mp4");
+        assert_eq!(
+            build_preview_args(video, 0.75, 2.5, "gif", "fps=12", Path::new("/out/in_preview.gif")),
+            argv(&["-y", "-ss", "0.750", "-t", "2.500", "-i", "/raw/in.mp4", "-vf", "fps=12", "-an", "-loop", "0", "/out/in_preview.gif"])
+        );
+        let webp = build_preview_args(video, 0.75, 2.5, "webp", "fps=12", Path::new("/out/in_preview.webp")).join(" ");
+        assert!(webp.ends_with("-an -loop 0 -c:v libwebp -quality 70 /out/in_preview.webp"), "{}", webp);
+    }
+
+    #[test]
+    fn segment_seeks_before_input() {
+        let settings = settings(serde_json::json!({}));
+        let args = build_segment_args(&segment(&settings, "scale=1080:1920"), Pass::Single, Path::new("/temp/seg.mp4"));
+        assert_eq!(args, argv(&[
+            "-y", "-progress", "pipe:1", "-nostats", "-ss", "1.000", "-i", "/raw/in.mp4", "-t", "3.000",
+            "-vf", "scale=1080:1920", "-c:v", "libx264", "-preset", "fast", "-crf", "23", "-pix_fmt", "yuv420p",
+            "-c:a", "aac", "-b:a", "128k", "/temp/seg.mp4",
+        ]));
+    }
+
+    #[test]
+    fn silent_still_loops_and_maps_generated_audio() {
+        let settings = settings(serde_json::json!({}));
+        let hold = ("tpad".to_string(), "apad".to_string());
+        let mut still = segment(&settings, "scale=1080:1920");
+        still.still_fps = Some(25.0);
+        still.has_audio = false;
+        still.audio_volume = Some(1.5);
+        still.hold = Some(&hold);
+        let args = build_segment_args(&still, Pass::Single, Path::new("/temp/seg.mp4")).join(" ");
+        assert!(args.contains("-loop 1 -framerate 25.000 -i /raw/in.mp4 -f lavfi -i anullsrc"), "{}", args);
+        assert!(!args.contains("-ss"), "{}", args);
+        assert!(args.contains("-vf scale=1080:1920,tpad -map 0:v -map 1:a -af volume=1.5,apad"), "{}", args);
+    }
+
+    #[test]
+    fn speed_window_retimes_the_middle_piece() {
+        let settings = settings(serde_json::json!({}));
+        let mut sped = segment(&settings, "null");
+        sped.speed = Some((1.0, 2.0, 2.0));
+        let args = build_segment_args(&sped, Pass::Single, Path::new("/temp/seg.mp4"));
+        let graph = &args[args.iter().position(|arg| arg == "-filter_complex").unwrap() + 1];
+        assert!(graph.starts_with("[0:v]null,split=3[vs0][vs1][vs2];[0:a]asplit=3"), "{}", graph);
+        assert!(graph.contains("[vs1]trim=1.000:2.000,setpts=(PTS-STARTPTS)/2[vp1]"), "{}", graph);
+        assert!(graph.ends_with("concat=n=3:v=1:a=1[vout][aout]"), "{}", graph);
+        assert!(!args.contains(&"-vf".to_string()));
+    }
+
+    #[test]
+    fn two_pass_writes_the_log_then_the_output() {
+        let settings = settings(serde_json::json!({ "target_bitrate": "2500k" }));
+        let log = Path::new("/temp/seg.passlog");
+        let first = build_segment_args(&segment(&settings, "null"), Pass::First(log), Path::new("/temp/seg.mp4"));
+        let second = build_segment_args(&segment(&settings, "null"), Pass::Second(log), Path::new("/temp/seg.mp4"));
+        assert!(first.join(" ").ends_with("-b:v 2500k -pix_fmt yuv420p -c:a aac -b:a 128k -pass 1 -passlogfile /temp/seg.passlog -f null -"));
+        assert!(second.join(" ").ends_with("-pass 2 -passlogfile /temp/seg.passlog /temp/seg.mp4"));
+    }
+
+    fn stitch<'a>(settings: &'a EncodeSettings, segments: &'a [PathBuf], joins: &'a [Option<Xfade>]) -> Stitch<'a> {
+        Stitch { segments, joins, encoder: VideoEncoder::Libx264, settings, output: Path::new("/temp/stitched.mp4") }
+    }
+
+    #[test]
+    fn stitch_mixes_transitions_and_hard_cuts() {
+        let settings = settings(serde_json::json!({}));
+        let segments: Vec<PathBuf> = ["/temp/seg0.mp4", "/temp/seg1.mp4", "/temp/seg2.mp4"].iter().map(PathBuf::from).collect();
+        let joins = [Some(Xfade { transition: "fade".to_string(), duration: 0.5, offset: 2.5 }), None];
+        let stitch = stitch(&settings, &segments, &joins);
+        assert_eq!(stitch_filter(&stitch), [
+            "[0:v][1:v]xfade=transition=fade:duration=0.500:offset=2.500[v1]",
+            "[0:a][1:a]acrossfade=d=0.500[a1]",
+            "[v1][2:v]concat=n=2:v=1:a=0[vout]",
+            "[a1][2:a]concat=n=2:v=0:a=1[aout]",
+        ].join(";"));
+        let args = build_stitch_args(&stitch).join(" ");
+        assert!(args.starts_with("-y -progress pipe:1 -nostats -i /temp/seg0.mp4 -i /temp/seg1.mp4 -i /temp/seg2.mp4 -filter_complex"), "{}", args);
+        assert!(args.ends_with("-map [vout] -map [aout] -c:v libx264 -preset fast -crf 23 -pix_fmt yuv420p -c:a aac -b:a 128k /temp/stitched.mp4"), "{}", args);
+    }
+
+    #[test]
+    fn stitch_uploads_after_the_last_join_for_vaapi() {
+        let settings = settings(serde_json::json!({}));
+        let segments: Vec<PathBuf> = ["/temp/seg0.mp4", "/temp/seg1.mp4"].iter().map(PathBuf::from).collect();
+        let joins = [None];
+        let mut stitch = stitch(&settings, &segments, &joins);
+        stitch.encoder = VideoEncoder::Vaapi;
+        assert_eq!(
+            stitch_filter(&stitch),
+            "[0:v][1:v]concat=n=2:v=1:a=0[vjoined];[0:a][1:a]concat=n=2:v=0:a=1[aout];[vjoined]format=nv12,hwupload[vout]"
+        );
+        let args = build_stitch_args(&stitch).join(" ");
+        assert!(args.starts_with("-y -progress pipe:1 -nostats -vaapi_device /dev/dri/renderD128 -i /temp/seg0.mp4"), "{}", args);
+    }
+
+    #[test]
+    fn resize_reencodes_video_at_the_target_bitrate_and_copies_audio() {
+        let settings = settings(serde_json::json!({ "target_bitrate": "900k" }));
+        let (input, output) = (Path::new("/out/in.mp4"), Path::new("/temp/resized.mp4"));
+        assert_eq!(build_resize_args(input, VideoEncoder::Libx264, &settings, output), argv(&[
+            "-y", "-progress", "pipe:1", "-nostats", "-i", "/out/in.mp4",
+            "-c:v", "libx264", "-preset", "fast", "-b:v", "900k", "-pix_fmt", "yuv420p", "-c:a", "copy", "/temp/resized.mp4",
+        ]));
+        let vaapi = build_resize_args(input, VideoEncoder::Vaapi, &settings, output).join(" ");
+        assert!(vaapi.contains("-i /out/in.mp4 -vf format=nv12,hwupload -c:v h264_vaapi -b:v 900k -c:a copy"), "{}", vaapi);
+    }
+
+    #[test]
+    fn final_mix_copies_video_without_a_graph() {
+        let settings = settings(serde_json::json!({}));
+        let mix = final_mix(&settings);
+        assert_eq!(final_mix_filter(&mix), None);
+        assert_eq!(build_concat_args(&mix), argv(&[
+            "-y", "-progress", "pipe:1", "-nostats", "-f", "concat", "-safe", "0", "-i", "/temp/list.txt",
+            "-af", "loudnorm=I=-14:TP=-1.5:LRA=11,aresample=48000", "-c:v", "copy", "-c:a", "aac", "-t", "6.000", "/out/in.mp4",
+        ]));
+    }
+
+    #[test]
+    fn final_mix_with_video_graph_and_chapters() {
+        let settings = settings(serde_json::json!({ "output_format": "webm/vp9" }));
+        let mut mix = final_mix(&settings);
+        mix.joined = JoinedVideo::Stitched(Path::new("/temp/stitched.mp4"));
+        mix.chapters = Some(Path::new("/temp/chapters.txt"));
+        mix.video_graph = Some(";[0:v]null[vout]");
+        mix.output = Path::new("/out/in.webm");
+        assert_eq!(final_mix_filter(&mix).as_deref(), Some("[0:a]loudnorm=I=-14:TP=-1.5:LRA=11,aresample=48000[aout];[0:v]null[vout]"));
+        let args = build_concat_args(&mix).join(" ");
+        assert!(args.contains("-i /temp/stitched.mp4 -i /temp/chapters.txt -filter_complex"), "{}", args);
+        assert!(args.contains("-map [vout] -map [aout] -c:v libvpx-vp9"), "{}", args);
+        assert!(args.ends_with("-map_metadata 1 -map_chapters 1 -c:a libopus -t 6.000 /out/in.webm"), "{}", args);
+    }
+
+    #[test]
+    fn audio_without_bgm_or_se_is_only_normalized() {
+        let settings = settings(serde_json::json!({}));
+        let mut mix = final_mix(&settings);
+        assert_eq!(final_mix_filter(&mix), None);
+        mix.video_graph = Some(";[mixed]asplit=2[aout][wave_a];[wave_a]showwaves[waves];[0:v][waves]overlay[vout]");
+        mix.mix_label = "[mixed]";
+        assert_eq!(
+            final_mix_filter(&mix).as_deref(),
+            Some("[0:a]loudnorm=I=-14:TP=-1.5:LRA=11,aresample=48000[mixed];[mixed]asplit=2[aout][wave_a];[wave_a]showwaves[waves];[0:v][waves]overlay[vout]")
+        );
+    }
+
+    #[test]
+    fn audio_mix_graphs() {
+        let settings = settings(serde_json::json!({}));
+        let mut panned = se("/se/whoosh.wav", 3.25);
+        panned.volume = 0.5;
+        panned.pan = Some("pan=stereo|c0=c0|c1=0*c1".to_string());
+        let cases = [
+            ("none", None, None),
+            (
+                "bgm only",
+                Some(audio_mix(Some(Bgm::Track { path: Path::new("/bgm/bed.mp3"), fade_in: Some(1.5) }), Vec::new())),
+                Some("[0:a]volume=1.3[v_in];[1:a]volume=0.08,afade=t=in:st=0:d=1.500[bgm];[v_in][bgm]amix=inputs=2:duration=first"),
+            ),
+            (
+                "se only",
+                Some(audio_mix(None, vec![se("/se/impact.wav", 2.0)])),
+                Some("[0:a]volume=1.3[v_in];[1:a]adelay=2000|2000,volume=0.8[se1];[v_in][se1]amix=inputs=2:duration=first"),
+            ),
+            (
+                "bgm and se",
+                Some(audio_mix(Some(Bgm::Track { path: Path::new("/bgm/bed.mp3"), fade_in: None }), vec![se("/se/impact.wav", 2.0)])),
+                Some("[0:a]volume=1.3[v_in];[1:a]volume=0.08[bgm];[2:a]adelay=2000|2000,volume=0.8[se2];\
+                      [v_in][bgm][se2]amix=inputs=3:duration=first"),
+            ),
+            (
+                "multiple se",
+                Some(audio_mix(None, vec![se("/se/impact.wav", 0.5), panned, se("/se/impact.wav", 5.0)])),
+                Some("[0:a]volume=1.3[v_in];[1:a]adelay=500|500,volume=0.8[se1];\
+                      [2:a]adelay=3250|3250,volume=0.5,pan=stereo|c0=c0|c1=0*c1[se2];[3:a]adelay=5000|5000,volume=0.8[se3];\
+                      [v_in][se1][se2][se3]amix=inputs=4:duration=first"),
+            ),
+        ];
+        for (name, audio, graph) in cases {
+            let mut mix = final_mix(&settings);
+            mix.audio = audio;
+            let expected = graph.map(|graph| format!("{},{}", graph, mix_tail()));
+            assert_eq!(final_mix_filter(&mix), expected, "{}", name);
+        }
+    }
+
+    #[test]
+    fn default_mix_end_to_end() {
+        let settings = settings(serde_json::json!({}));
+        let mut mix = final_mix(&settings);
+        mix.audio = Some(audio_mix(Some(Bgm::Track { path: Path::new("/bgm/bed.mp3"), fade_in: None }), vec![se("/se/impact.wav", 2.0)]));
+        assert_eq!(build_concat_args(&mix), argv(&[
+            "-y", "-progress", "pipe:1", "-nostats", "-f", "concat", "-safe", "0", "-i", "/temp/list.txt",
+            "-i", "/bgm/bed.mp3", "-i", "/se/impact.wav",
+            "-filter_complex",
+            "[0:a]volume=1.3[v_in];[1:a]volume=0.08[bgm];[2:a]adelay=2000|2000,volume=0.8[se2];\
+             [v_in][bgm][se2]amix=inputs=3:duration=first,loudnorm=I=-14:TP=-1.5:LRA=11,aresample=48000,\
+             afade=t=out:st=4.000:d=2.000,alimiter=limit=0.891:attack=5:release=50:level=0[aout]",
+            "-map", "0:v", "-map", "[aout]", "-c:v", "copy", "-c:a", "aac", "-t", "6.000", "/out/in.mp4",
+        ]));
+    }
+
+    #[test]
+    fn audio_with_ducked_bgm_sections_after_chapters() {
+        let settings = settings(serde_json::json!({}));
+        let sections = [
+            BgmSection { path: PathBuf::from("/bgm/a.mp3"), start: 0.0, end: 3.0, fade: 0.5 },
+            BgmSection { path: PathBuf::from("/bgm/b.mp3"), start: 3.0, end: 6.0, fade: 0.0 },
+        ];
+        let mut mix = final_mix(&settings);
+        mix.chapters = Some(Path::new("/temp/chapters.txt"));
+        let mut audio = audio_mix(Some(Bgm::Sections(&sections)), vec![se("/se/impact.wav", 2.0)]);
+        audio.ducking = true;
+        audio.se_ducking = true;
+        audio.bgm_loop = true;
+        audio.limiter = false;
+        audio.fade_out = 0.0;
+        mix.audio = Some(audio);
+        assert_eq!(
+            final_mix_filter(&mix).unwrap(),
+            "[0:a]volume=1.3,asplit=2[v_in][duck_key];\
+             [2:a]atrim=duration=3.000,asetpts=PTS-STARTPTS,volume=0.08,afade=t=in:st=0:d=0.500,afade=t=out:st=2.500:d=0.500,adelay=0:all=1[bgm_s0];\
+             [3:a]atrim=duration=3.000,asetpts=PTS-STARTPTS,volume=0.08,adelay=3000:all=1[bgm_s1];\
+             [bgm_s0][bgm_s1]amix=inputs=2:duration=longest:normalize=0[bgm_raw];\
+             [bgm_raw][duck_key]sidechaincompress=threshold=0.02:ratio=8:attack=20:release=400[bgm_full];\
+             [bgm_full]volume='(1-0.6*clip(min((t-1.950)/0.05,(2.600-t)/0.3),0,1))':eval=frame[bgm];\
+             [4:a]adelay=2000|2000,volume=0.8[se4];[v_in][bgm][se4]amix=inputs=3:duration=first,loudnorm=I=-14:TP=-1.5:LRA=11,aresample=48000[aout]"
+        );
+        let args = build_concat_args(&mix).join(" ");
+        assert!(args.contains("-i /temp/chapters.txt -stream_loop -1 -i /bgm/a.mp3 -stream_loop -1 -i /bgm/b.mp3 -i /se/impact.wav"), "{}", args);
+    }
+}
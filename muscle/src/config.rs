@@ -0,0 +1,222 @@
+use anyhow::{Context, Result};
+use serde::Deserialize;
+use serde_json::{Map, Value};
+use std::path::{Path, PathBuf};
+
+use crate::analysis::Analysis;
+use crate::ffmpeg::VideoEncoder;
+use crate::logging::log_json;
+use crate::se::SeMap;
+use crate::upload::S3Config;
+
+// Defaults match the Docker volume layout; each can be overridden in the config file and
+// again via NUE_* env vars
+const RAW_DIR: &str = "/app/data/raw";
+const JSON_DIR: &str = "/app/data/json";
+const OUTPUT_DIR: &str = "/app/data/output";
+const TEMP_DIR: &str = "/app/data/temp";
+const BGM_PATH: &str = "/app/data/bgm/default_bgm.mp3";
+const SE_DIR: &str = "/app/data/se";
+const ASSETS_DIR: &str = "/app/data/assets";
+const DEFAULT_FFMPEG_RETRIES: u32 = 2;
+// Looked for in the working directory when NUE_CONFIG isn't set
+const CONFIG_FILES: [&str; 2] = ["nue.toml", "nue.json"];
+
+// nue.toml / nue.json: the settings below (NUE_* env vars still win), plus a `defaults`
+// table of Analysis fields used when an instruction leaves them out
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ConfigFile {
+    raw_dir: Option<PathBuf>,
+    json_dir: Option<PathBuf>,
+    output_dir: Option<PathBuf>,
+    temp_dir: Option<PathBuf>,
+    bgm_path: Option<PathBuf>,
+    se_dir: Option<PathBuf>,
+    assets_dir: Option<PathBuf>,
+    ffmpeg_retries: Option<u32>,
+    hwaccel: Option<String>,
+    webhook_url: Option<String>,
+    caption_textfile: Option<bool>,
+    watch_recursive: Option<bool>,
+    archive_json: Option<bool>,
+    keep_temp: Option<bool>,
+    #[serde(default)]
+    defaults: Map<String, Value>,
+}
+
+impl ConfigFile {
+    fn parse(path: &Path) -> Result<Self> {
+        let text = std::fs::read_to_string(path).with_context(|| format!("Cannot read config file {:?}", path))?;
+        let parsed = if path.extension().is_some_and(|ext| ext == "toml") {
+            toml::from_str(&text).map_err(anyhow::Error::from)
+        } else {
+            serde_json::from_str(&text).map_err(anyhow::Error::from)
+        };
+        parsed.with_context(|| format!("Bad config file {:?}", path))
+    }
+}
+
+// Everything the library needs to know about its environment, so callers
+// (the watcher binary, tests, other front ends) decide where files live.
+#[derive(Debug, Clone)]
+pub struct Config {
+    pub raw_dir: PathBuf,
+    pub json_dir: PathBuf,
+    pub output_dir: PathBuf,
+    pub temp_dir: PathBuf,
+    pub bgm_path: PathBuf,
+    pub se_dir: PathBuf,
+    // Images referenced by name from the Analysis (caption backgrounds, ...)
+    pub assets_dir: PathBuf,
+    // Log ffmpeg commands instead of running them
+    pub dry_run: bool,
+    // Extra attempts for a failed ffmpeg invocation (NUE_FFMPEG_RETRIES)
+    pub ffmpeg_retries: u32,
+    // Tag -> SE file mapping (se_map.json in se_dir, else the built-in mapping)
+    pub se_map: SeMap,
+    // Segment/transition encoder (NUE_HWACCEL = nvenc | vaapi | videotoolbox, default libx264)
+    pub video_encoder: VideoEncoder,
+    // Upload target for finished renders (NUE_S3_BUCKET and friends); None keeps files local
+    pub s3: Option<S3Config>,
+    // Default webhook notified when a render finishes (NUE_WEBHOOK_URL)
+    pub webhook_url: Option<String>,
+    // Pass caption text to drawtext through temp files instead of inline (NUE_CAPTION_TEXTFILE)
+    pub caption_textfile: bool,
+    // Watch JSON_DIR subfolders too; outputs mirror the subfolder under OUTPUT_DIR (NUE_WATCH_RECURSIVE)
+    pub watch_recursive: bool,
+    // Move handled instructions to JSON_DIR/processed or /failed; NUE_ARCHIVE_JSON=0 leaves them in place
+    pub archive_json: bool,
+    // Leave each job's segments, concat list etc. in TEMP_DIR/<job prefix>/ (NUE_KEEP_TEMP)
+    pub keep_temp: bool,
+    // Analysis fields from the config file's `defaults`, filled into instructions that omit them
+    pub analysis_defaults: Map<String, Value>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            raw_dir: PathBuf::from(RAW_DIR),
+            json_dir: PathBuf::from(JSON_DIR),
+            output_dir: PathBuf::from(OUTPUT_DIR),
+            temp_dir: PathBuf::from(TEMP_DIR),
+            bgm_path: PathBuf::from(BGM_PATH),
+            se_dir: PathBuf::from(SE_DIR),
+            assets_dir: PathBuf::from(ASSETS_DIR),
+            dry_run: false,
+            ffmpeg_retries: DEFAULT_FFMPEG_RETRIES,
+            se_map: SeMap::default(),
+            video_encoder: VideoEncoder::default(),
+            s3: None,
+            webhook_url: None,
+            caption_textfile: false,
+            watch_recursive: false,
+            archive_json: true,
+            keep_temp: false,
+            analysis_defaults: Map::new(),
+        }
+    }
+}
+
+impl Config {
+    // The config file named by NUE_CONFIG, else nue.toml or nue.json in the working directory
+    // (neither is fine), with env vars on top. A named or found file that doesn't parse is an
+    // error rather than a silent fallback to the defaults.
+    pub fn load() -> Result<Self> {
+        let path = match std::env::var("NUE_CONFIG") {
+            Ok(path) if !path.trim().is_empty() => Some(PathBuf::from(path)),
+            _ => CONFIG_FILES.iter().map(PathBuf::from).find(|path| path.exists()),
+        };
+        let Some(path) = path else {
+            return Ok(Self::from_env());
+        };
+        let file = ConfigFile::parse(&path)?;
+        log_json("INFO", &format!("Loaded config file ({} analysis defaults)", file.defaults.len()), Some("config_file"), path.to_str());
+        Ok(Self::from_file_and_env(file))
+    }
+
+    pub fn from_env() -> Self {
+        Self::from_file_and_env(ConfigFile::default())
+    }
+
+    fn from_file_and_env(file: ConfigFile) -> Self {
+        fn env_path(key: &str, file: Option<PathBuf>, default: &str) -> PathBuf {
+            match std::env::var(key) {
+                Ok(value) if !value.trim().is_empty() => PathBuf::from(value),
+                _ => file.unwrap_or_else(|| PathBuf::from(default)),
+            }
+        }
+
+        fn env_flag(key: &str) -> Option<bool> {
+            std::env::var(key)
+                .ok()
+                .map(|v| v == "1" || v.eq_ignore_ascii_case("true"))
+        }
+
+        let dry_run = env_flag("NUE_DRY_RUN").unwrap_or(false);
+
+        let ffmpeg_retries = std::env::var("NUE_FFMPEG_RETRIES")
+            .ok()
+            .and_then(|v| v.trim().parse().ok())
+            .or(file.ffmpeg_retries)
+            .unwrap_or(DEFAULT_FFMPEG_RETRIES);
+
+        // Probed once here; dry runs never touch ffmpeg, so they take the request as-is
+        let video_encoder = match std::env::var("NUE_HWACCEL").ok().or(file.hwaccel) {
+            Some(value) => match VideoEncoder::from_hwaccel(&value) {
+                Some(encoder) if dry_run => encoder,
+                Some(encoder) => encoder.probe(),
+                None => {
+                    log_json("WARN", &format!("Unknown NUE_HWACCEL '{}', using libx264", value), Some("hwaccel_fallback"), None);
+                    VideoEncoder::Libx264
+                }
+            },
+            None => VideoEncoder::Libx264,
+        };
+
+        let se_dir = env_path("NUE_SE_DIR", file.se_dir, SE_DIR);
+        let se_map = SeMap::load(&se_dir);
+
+        Config {
+            raw_dir: env_path("NUE_RAW_DIR", file.raw_dir, RAW_DIR),
+            json_dir: env_path("NUE_JSON_DIR", file.json_dir, JSON_DIR),
+            output_dir: env_path("NUE_OUTPUT_DIR", file.output_dir, OUTPUT_DIR),
+            temp_dir: env_path("NUE_TEMP_DIR", file.temp_dir, TEMP_DIR),
+            bgm_path: env_path("NUE_BGM_PATH", file.bgm_path, BGM_PATH),
+            se_dir,
+            assets_dir: env_path("NUE_ASSETS_DIR", file.assets_dir, ASSETS_DIR),
+            dry_run,
+            ffmpeg_retries,
+            se_map,
+            video_encoder,
+            s3: S3Config::from_env(),
+            webhook_url: std::env::var("NUE_WEBHOOK_URL").ok().or(file.webhook_url).filter(|v| !v.trim().is_empty()),
+            caption_textfile: env_flag("NUE_CAPTION_TEXTFILE").or(file.caption_textfile).unwrap_or(false),
+            watch_recursive: env_flag("NUE_WATCH_RECURSIVE").or(file.watch_recursive).unwrap_or(false),
+            archive_json: std::env::var("NUE_ARCHIVE_JSON")
+                .ok()
+                .map(|v| !(v == "0" || v.eq_ignore_ascii_case("false")))
+                .or(file.archive_json)
+                .unwrap_or(true),
+            keep_temp: env_flag("NUE_KEEP_TEMP").or(file.keep_temp).unwrap_or(false),
+            analysis_defaults: file.defaults,
+        }
+    }
+
+    // An instruction with the config file's defaults filled in where it has no value (a
+    // missing or null field)
+    pub fn parse_analysis(&self, content: &str) -> serde_json::Result<Analysis> {
+        if self.analysis_defaults.is_empty() {
+            return serde_json::from_str(content);
+        }
+        let mut value: Value = serde_json::from_str(content)?;
+        if let Value::Object(fields) = &mut value {
+            for (key, default) in &self.analysis_defaults {
+                if fields.get(key).filter(|v| !v.is_null()).is_none() {
+                    fields.insert(key.clone(), default.clone());
+                }
+            }
+        }
+        serde_json::from_value(value)
+    }
+}
@@ -0,0 +1,383 @@
+use anyhow::Result;
+use std::io::{BufRead, BufReader, Read};
+use std::fmt;
+use std::process::{Command, ExitStatus, Output, Stdio};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use crate::analysis::Analysis;
+use crate::logging::{log_json, log_progress};
+
+const RETRY_BASE_DELAY: Duration = Duration::from_secs(1);
+// Backoff stops doubling here (64s), however many retries are configured
+const MAX_RETRY_DOUBLINGS: u32 = 6;
+const STDERR_TAIL_LINES: usize = 10;
+
+// Wall time spent inside ffmpeg runs since process start, for throughput metrics
+static FFMPEG_MILLIS: AtomicU64 = AtomicU64::new(0);
+
+// ffmpeg children still running, by pid (which is also their process group ID)
+static RUNNING: Mutex<Vec<u32>> = Mutex::new(Vec::new());
+
+// Keeps a child in RUNNING until it has been waited for
+struct Running(u32);
+
+impl Running {
+    fn new(pid: u32) -> Self {
+        RUNNING.lock().unwrap_or_else(|e| e.into_inner()).push(pid);
+        Running(pid)
+    }
+}
+
+impl Drop for Running {
+    fn drop(&mut self) {
+        RUNNING.lock().unwrap_or_else(|e| e.into_inner()).retain(|&pid| pid != self.0);
+    }
+}
+
+// SIGKILL every running ffmpeg's process group, for a shutdown that can't wait any longer
+pub(crate) fn kill_running() {
+    for &pid in RUNNING.lock().unwrap_or_else(|e| e.into_inner()).iter() {
+        // SAFETY: kill(2) takes no pointers; a group that already exited just yields ESRCH
+        unsafe { libc::kill(-(pid as libc::pid_t), libc::SIGKILL) };
+    }
+}
+
+// Run a command to completion like Command::output, but visible to kill_running
+pub(crate) fn run(cmd: &mut Command) -> Result<Output> {
+    let child = cmd.stdout(Stdio::piped()).stderr(Stdio::piped()).spawn()?;
+    let _running = Running::new(child.id());
+    Ok(child.wait_with_output()?)
+}
+
+// Total seconds spent running ffmpeg (all attempts, successful or not) in this process
+pub fn ffmpeg_seconds() -> f64 {
+    FFMPEG_MILLIS.load(Ordering::Relaxed) as f64 / 1000.0
+}
+
+// A failed ffmpeg run. Kept as a typed error (inside anyhow) so failure logs can report
+// the exit code, e.g. 137 for an OOM kill vs 1 for a bad argument.
+#[derive(Debug)]
+pub struct FfmpegError {
+    pub what: String,
+    pub exit_code: Option<i32>,
+    pub stderr: String,
+}
+
+impl FfmpegError {
+    pub(crate) fn from_output(what: impl Into<String>, output: &Output) -> Self {
+        FfmpegError {
+            what: what.into(),
+            exit_code: exit_code(&output.status),
+            stderr: String::from_utf8_lossy(&output.stderr).into_owned(),
+        }
+    }
+
+    // Last few stderr lines, where ffmpeg puts the actual reason
+    pub fn stderr_tail(&self) -> String {
+        let lines: Vec<&str> = self.stderr.trim_end().lines().collect();
+        lines[lines.len().saturating_sub(STDERR_TAIL_LINES)..].join("\n")
+    }
+}
+
+impl fmt::Display for FfmpegError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.what, self.stderr)
+    }
+}
+
+impl std::error::Error for FfmpegError {}
+
+// The process exit code, or 128 + signal when it was killed (the shell convention)
+pub(crate) fn exit_code(status: &ExitStatus) -> Option<i32> {
+    #[cfg(unix)]
+    {
+        use std::os::unix::process::ExitStatusExt;
+        status.code().or_else(|| status.signal().map(|signal| 128 + signal))
+    }
+    #[cfg(not(unix))]
+    {
+        status.code()
+    }
+}
+
+// H.264 encoder for segment and transition re-encodes. libx264 unless NUE_HWACCEL
+// asks for a hardware encoder that this ffmpeg build actually has.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum VideoEncoder {
+    #[default]
+    Libx264,
+    Nvenc,
+    Vaapi,
+    VideoToolbox,
+}
+
+pub(crate) const DEFAULT_CRF: u8 = 23;
+pub(crate) const DEFAULT_PRESET: &str = "fast";
+pub(crate) const DEFAULT_AUDIO_BITRATE: &str = "128k";
+const PREVIEW_PRESET: &str = "ultrafast";
+pub(crate) const X264_PRESETS: [&str; 9] =
+    ["ultrafast", "superfast", "veryfast", "faster", "fast", "medium", "slow", "slower", "veryslow"];
+
+// Container and codecs of the rendered file ("container/codec" in Analysis.output_format).
+// Intermediate segments use the same video codec so the final mix can still copy it; their
+// audio stays AAC since the final mix re-encodes audio anyway.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum OutputFormat {
+    #[default]
+    Mp4H264,
+    Mp4Hevc,
+    WebmVp9,
+}
+
+pub(crate) const OUTPUT_FORMATS: [&str; 3] = ["mp4/h264", "mp4/hevc", "webm/vp9"];
+
+impl OutputFormat {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "mp4/h264" => Some(OutputFormat::Mp4H264),
+            "mp4/hevc" => Some(OutputFormat::Mp4Hevc),
+            "webm/vp9" => Some(OutputFormat::WebmVp9),
+            _ => None,
+        }
+    }
+
+    pub fn extension(&self) -> &'static str {
+        match self {
+            OutputFormat::WebmVp9 => "webm",
+            _ => "mp4",
+        }
+    }
+
+    // Audio codec for the final mix
+    pub fn audio_codec(&self) -> &'static str {
+        match self {
+            OutputFormat::WebmVp9 => "libopus",
+            _ => "aac",
+        }
+    }
+}
+
+// Quality knobs for segment and transition encodes, from the Analysis with repo defaults
+pub(crate) struct EncodeSettings {
+    pub format: OutputFormat,
+    pub crf: u8,
+    pub preset: String,
+    pub audio_bitrate: String,
+    // Two-pass bitrate target like "2500k"; None keeps the crf path
+    pub target_bitrate: Option<String>,
+}
+
+impl EncodeSettings {
+    pub fn from_analysis(analysis: &Analysis) -> Self {
+        // Previews trade quality for speed: fastest preset, single pass
+        if analysis.is_preview() {
+            return EncodeSettings {
+                format: analysis.encode_format(),
+                crf: analysis.crf.unwrap_or(DEFAULT_CRF),
+                preset: PREVIEW_PRESET.to_string(),
+                audio_bitrate: analysis.audio_bitrate.clone().unwrap_or_else(|| DEFAULT_AUDIO_BITRATE.to_string()),
+                target_bitrate: None,
+            };
+        }
+        EncodeSettings {
+            format: analysis.encode_format(),
+            crf: analysis.crf.unwrap_or(DEFAULT_CRF),
+            preset: analysis.preset.clone().unwrap_or_else(|| DEFAULT_PRESET.to_string()),
+            audio_bitrate: analysis.audio_bitrate.clone().unwrap_or_else(|| DEFAULT_AUDIO_BITRATE.to_string()),
+            target_bitrate: analysis.target_bitrate.clone(),
+        }
+    }
+
+    // Only libx264 and libvpx-vp9 support -pass here; the others use the bitrate in a single pass
+    pub fn two_pass(&self, encoder: VideoEncoder) -> bool {
+        self.target_bitrate.is_some() && matches!(encoder.codec_for(self.format), "libx264" | "libvpx-vp9")
+    }
+}
+
+const VAAPI_DEVICE: &str = "/dev/dri/renderD128";
+
+impl VideoEncoder {
+    // NUE_HWACCEL value -> encoder ("nvenc", "vaapi", "videotoolbox")
+    pub fn from_hwaccel(name: &str) -> Option<Self> {
+        match name.trim().to_lowercase().as_str() {
+            "nvenc" => Some(VideoEncoder::Nvenc),
+            "vaapi" => Some(VideoEncoder::Vaapi),
+            "videotoolbox" => Some(VideoEncoder::VideoToolbox),
+            "" | "none" | "libx264" => Some(VideoEncoder::Libx264),
+            _ => None,
+        }
+    }
+
+    pub fn codec(&self) -> &'static str {
+        match self {
+            VideoEncoder::Libx264 => "libx264",
+            VideoEncoder::Nvenc => "h264_nvenc",
+            VideoEncoder::Vaapi => "h264_vaapi",
+            VideoEncoder::VideoToolbox => "h264_videotoolbox",
+        }
+    }
+
+    // The encoder for an output format. Hardware families are probed for H.264 only and
+    // assumed to have HEVC too; only VAAPI encodes VP9 in hardware, so the rest use libvpx-vp9.
+    pub fn codec_for(&self, format: OutputFormat) -> &'static str {
+        match (format, self) {
+            (OutputFormat::Mp4H264, _) => self.codec(),
+            (OutputFormat::Mp4Hevc, VideoEncoder::Libx264) => "libx265",
+            (OutputFormat::Mp4Hevc, VideoEncoder::Nvenc) => "hevc_nvenc",
+            (OutputFormat::Mp4Hevc, VideoEncoder::Vaapi) => "hevc_vaapi",
+            (OutputFormat::Mp4Hevc, VideoEncoder::VideoToolbox) => "hevc_videotoolbox",
+            (OutputFormat::WebmVp9, VideoEncoder::Vaapi) => "vp9_vaapi",
+            (OutputFormat::WebmVp9, _) => "libvpx-vp9",
+        }
+    }
+
+    // Global options that must precede the first -i
+    pub(crate) fn input_args(&self) -> Vec<&'static str> {
+        match self {
+            VideoEncoder::Vaapi => vec!["-vaapi_device", VAAPI_DEVICE],
+            _ => Vec::new(),
+        }
+    }
+
+    // Appended to the end of the video filter chain so frames reach the GPU encoder
+    pub(crate) fn upload_filter(&self) -> Option<&'static str> {
+        match self {
+            VideoEncoder::Vaapi => Some("format=nv12,hwupload"),
+            _ => None,
+        }
+    }
+
+    // Codec and quality flags. crf maps onto each encoder's constant-quality knob; the
+    // x264 preset only applies to libx264/libx265. A target bitrate replaces the quality knob.
+    pub(crate) fn output_args(&self, settings: &EncodeSettings) -> Vec<String> {
+        let crf = settings.crf.to_string();
+        let codec = self.codec_for(settings.format);
+        let args: Vec<&str> = match (codec, settings.target_bitrate.as_deref()) {
+            // -b:v 0 makes libvpx-vp9 constant quality; row-mt and cpu-used keep it near x264 "fast"
+            ("libvpx-vp9", bitrate) => {
+                let quality = match bitrate {
+                    Some(bitrate) => vec!["-b:v", bitrate],
+                    None => vec!["-crf", &crf, "-b:v", "0"],
+                };
+                quality.into_iter().chain(["-deadline", "good", "-cpu-used", "4", "-row-mt", "1", "-pix_fmt", "yuv420p"]).collect()
+            }
+            ("vp9_vaapi", Some(bitrate)) => vec!["-b:v", bitrate],
+            ("vp9_vaapi", None) => vec!["-global_quality", &crf],
+            _ => self.h264_args(settings, &crf),
+        };
+        // hvc1 so Apple players accept HEVC in MP4
+        let tag = if settings.format == OutputFormat::Mp4Hevc { vec!["-tag:v", "hvc1"] } else { Vec::new() };
+        ["-c:v", codec].into_iter().chain(args).chain(tag).map(String::from).collect()
+    }
+
+    // Flags for the H.264 encoders, which their HEVC siblings (libx265 included) share
+    fn h264_args<'a>(&self, settings: &'a EncodeSettings, crf: &'a str) -> Vec<&'a str> {
+        match (self, settings.target_bitrate.as_deref()) {
+            (VideoEncoder::Libx264, Some(bitrate)) => vec!["-preset", &settings.preset, "-b:v", bitrate, "-pix_fmt", "yuv420p"],
+            (VideoEncoder::Libx264, None) => vec!["-preset", &settings.preset, "-crf", crf, "-pix_fmt", "yuv420p"],
+            (VideoEncoder::Nvenc, Some(bitrate)) => vec!["-preset", "p4", "-rc", "vbr", "-b:v", bitrate, "-pix_fmt", "yuv420p"],
+            (VideoEncoder::Nvenc, None) => vec!["-preset", "p4", "-rc", "vbr", "-cq", crf, "-pix_fmt", "yuv420p"],
+            (VideoEncoder::Vaapi, Some(bitrate)) => vec!["-b:v", bitrate],
+            (VideoEncoder::Vaapi, None) => vec!["-qp", crf],
+            (VideoEncoder::VideoToolbox, bitrate) => vec!["-b:v", bitrate.unwrap_or("8M"), "-pix_fmt", "yuv420p"],
+        }
+    }
+
+    // Check `ffmpeg -encoders` once; a missing hardware encoder falls back to libx264
+    pub fn probe(self) -> Self {
+        if self == VideoEncoder::Libx264 {
+            return self;
+        }
+        let available = Command::new("ffmpeg")
+            .args(["-hide_banner", "-encoders"])
+            .output()
+            .map(|out| String::from_utf8_lossy(&out.stdout).contains(self.codec()))
+            .unwrap_or(false);
+        if available {
+            log_json("INFO", &format!("Using hardware encoder {}", self.codec()), Some("hwaccel_enabled"), None);
+            self
+        } else {
+            log_json("WARN", &format!("Encoder {} not available, falling back to libx264", self.codec()), Some("hwaccel_fallback"), None);
+            VideoEncoder::Libx264
+        }
+    }
+}
+
+// Arguments that make ffmpeg write machine-readable progress blocks to stdout.
+// Must be added before the output path.
+pub(crate) const PROGRESS_ARGS: [&str; 3] = ["-progress", "pipe:1", "-nostats"];
+
+// Run an ffmpeg command that was given PROGRESS_ARGS, logging a progress entry per
+// block. `duration` is the expected output length in seconds, used for the percentage.
+pub(crate) fn run_with_progress(cmd: &mut Command, duration: f64, label: &str) -> Result<Output> {
+    let mut child = cmd.stdout(Stdio::piped()).stderr(Stdio::piped()).spawn()?;
+    let _running = Running::new(child.id());
+
+    // Drain stderr on its own thread so a chatty ffmpeg can't block on a full pipe
+    let mut stderr = child.stderr.take().expect("stderr is piped");
+    let stderr_reader = std::thread::spawn(move || {
+        let mut buf = Vec::new();
+        let _ = stderr.read_to_end(&mut buf);
+        buf
+    });
+
+    let stdout = BufReader::new(child.stdout.take().expect("stdout is piped"));
+    let mut out_time = 0.0;
+    let mut frame = 0u64;
+    for line in stdout.lines().map_while(Result::ok) {
+        let Some((key, value)) = line.split_once('=') else { continue };
+        match key {
+            // Despite the name, out_time_ms is also in microseconds
+            "out_time_us" | "out_time_ms" => {
+                if let Ok(us) = value.trim().parse::<f64>() {
+                    out_time = us / 1_000_000.0;
+                }
+            }
+            "frame" => frame = value.trim().parse().unwrap_or(frame),
+            "progress" => {
+                let percent = if duration > 0.0 {
+                    (out_time / duration * 100.0).clamp(0.0, 100.0)
+                } else {
+                    0.0
+                };
+                log_progress(label, percent, frame, out_time);
+            }
+            _ => {}
+        }
+    }
+
+    let status = child.wait()?;
+    let stderr = stderr_reader.join().unwrap_or_default();
+    Ok(Output { status, stdout: Vec::new(), stderr })
+}
+
+// Run an ffmpeg invocation, retrying spawn errors and non-zero exits up to `retries`
+// extra times with exponential backoff (1s, 2s, 4s, ... up to 64s). The last failing Output is
+// returned as-is so callers keep their own error reporting.
+pub(crate) fn run_with_retry<F>(label: &str, retries: u32, mut run: F) -> Result<Output>
+where
+    F: FnMut() -> Result<Output>,
+{
+    let mut attempt = 0;
+    loop {
+        let started = Instant::now();
+        let result = run();
+        FFMPEG_MILLIS.fetch_add(started.elapsed().as_millis() as u64, Ordering::Relaxed);
+        let failure = match &result {
+            Ok(output) if output.status.success() => return result,
+            Ok(output) => output.status.to_string(),
+            Err(e) => format!("spawn error: {}", e),
+        };
+
+        if attempt >= retries {
+            return result;
+        }
+
+        let delay = RETRY_BASE_DELAY * 2u32.pow(attempt.min(MAX_RETRY_DOUBLINGS));
+        attempt += 1;
+        log_json("WARN", &format!("{} failed ({}), retry {}/{} in {:?}", label, failure, attempt, retries, delay), Some("ffmpeg_retry"), None);
+        std::thread::sleep(delay);
+    }
+}
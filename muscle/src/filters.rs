@@ -0,0 +1,698 @@
+use std::path::Path;
+
+use crate::analysis::{CaptionStyle, Thumbnail};
+
+// Scale so the source covers width x height, then crop; focus (0..1) picks the horizontal offset
+pub(crate) fn get_cover_filter(width: u32, height: u32, focus: f64) -> String {
+    format!(
+        "scale={w}:{h}:force_original_aspect_ratio=increase,crop={w}:{h}:(iw-{w})*{f}:(ih-{h})/2",
+        w = width, h = height, f = focus
+    )
+}
+
+pub(crate) const FIT_MODES: [&str; 2] = ["crop", "blur_pad"];
+
+// Fit the whole source inside width x height over a blurred, cropped copy of itself
+// (the "blurred pillarbox" look) instead of cropping content away
+pub(crate) fn get_blur_pad_filter(width: u32, height: u32) -> String {
+    format!(
+        "split=2[bg][fg];[bg]scale={w}:{h}:force_original_aspect_ratio=increase,crop={w}:{h},boxblur=20:5[bgb];\
+         [fg]scale={w}:{h}:force_original_aspect_ratio=decrease[fgs];[bgb][fgs]overlay=(W-w)/2:(H-h)/2,setsar=1",
+        w = width, h = height
+    )
+}
+
+// Cover filter whose focus moves linearly from `from` to `to` over `duration` seconds
+pub(crate) fn get_cover_pan_filter(width: u32, height: u32, from: f64, to: f64, duration: f64) -> String {
+    format!(
+        "scale={w}:{h}:force_original_aspect_ratio=increase,crop={w}:{h}:x='(iw-{w})*({f}+({d:.4})*min(t/{t:.3},1))':y=(ih-{h})/2",
+        w = width, h = height, f = from, d = to - from, t = duration
+    )
+}
+
+const DEFAULT_THUMBNAIL_FONT_SIZE: u32 = 120;
+
+// Color names passed to drawtext as-is; anything else must be #RRGGBB or 0xRRGGBB
+const NAMED_COLORS: [&str; 9] = ["white", "black", "yellow", "cyan", "red", "green", "blue", "magenta", "orange"];
+
+// A named color (any case) or a #RRGGBB / 0xRRGGBB hex color
+pub(crate) fn is_valid_color(value: &str) -> bool {
+    let value = value.trim();
+    NAMED_COLORS.contains(&value.to_lowercase().as_str()) || hex_digits(value).is_some()
+}
+
+fn hex_digits(value: &str) -> Option<&str> {
+    let digits = value.strip_prefix('#').or_else(|| value.strip_prefix("0x")).or_else(|| value.strip_prefix("0X"))?;
+    (digits.len() == 6 && digits.chars().all(|c| c.is_ascii_hexdigit())).then_some(digits)
+}
+
+// drawtext color for a named or hex color, normalized to lowercase names and 0xRRGGBB.
+// Unset or malformed values get the default.
+pub(crate) fn resolve_color(value: Option<&str>, default: &str) -> String {
+    let Some(value) = value.map(str::trim) else {
+        return default.to_string();
+    };
+    if let Some(digits) = hex_digits(value) {
+        return format!("0x{}", digits.to_uppercase());
+    }
+    let name = value.to_lowercase();
+    if NAMED_COLORS.contains(&name.as_str()) {
+        name
+    } else {
+        default.to_string()
+    }
+}
+
+pub(crate) const SANS_FONT: &str = "/usr/share/fonts/opentype/noto/NotoSansCJK-Bold.ttc";
+pub(crate) const SERIF_FONT: &str = "/usr/share/fonts/opentype/noto/NotoSerifCJK-Bold.ttc";
+const FONT_EXTENSIONS: [&str; 3] = ["ttf", "otf", "ttc"];
+
+// True when a font is given as a font file path rather than a preset name
+pub(crate) fn is_font_path(font: &str) -> bool {
+    Path::new(font)
+        .extension()
+        .and_then(|ext| ext.to_str())
+        .is_some_and(|ext| FONT_EXTENSIONS.contains(&ext.to_lowercase().as_str()))
+}
+
+// Font file for a preset ("sans" default, "serif") or a custom .ttf/.otf/.ttc path.
+// Paths that don't exist fall back to the sans preset.
+pub(crate) fn resolve_font(font: Option<&str>) -> &str {
+    match font.unwrap_or("sans") {
+        "serif" => SERIF_FONT,
+        path if is_font_path(path) && Path::new(path).is_file() => path,
+        _ => SANS_FONT,
+    }
+}
+
+// Color emoji font (fonts-noto-color-emoji); the Noto CJK fonts have no emoji glyphs
+const EMOJI_FONT: &str = "/usr/share/fonts/truetype/noto/NotoColorEmoji.ttf";
+
+// Pictographic blocks (including regional indicator flags); joiners and variation selectors only glue
+// emoji sequences together and count as neither emoji nor text
+fn is_emoji(c: char) -> bool {
+    matches!(c as u32, 0x1F000..=0x1FAFF | 0x2600..=0x27BF | 0x2B00..=0x2BFF)
+}
+
+fn is_emoji_joiner(c: char) -> bool {
+    matches!(c, '\u{200D}' | '\u{FE0F}' | '\u{20E3}')
+}
+
+// True when the text mixes emoji with regular characters, i.e. the emoji will be dropped
+pub(crate) fn has_mixed_emoji(text: &str) -> bool {
+    text.chars().any(is_emoji) && text.chars().any(|c| !c.is_whitespace() && !is_emoji(c) && !is_emoji_joiner(c))
+}
+
+// drawtext can't switch fonts mid-string, so text made only of emoji is drawn with the emoji
+// font and emoji inside regular text are stripped rather than rendered as tofu boxes
+pub(crate) fn select_font<'a>(text: &str, font: &'a str) -> (&'a str, String) {
+    if !text.chars().any(is_emoji) {
+        return (font, text.to_string());
+    }
+    if !has_mixed_emoji(text) {
+        return (EMOJI_FONT, text.to_string());
+    }
+    let stripped: String = text.chars().filter(|&c| !is_emoji(c) && !is_emoji_joiner(c)).collect();
+    (font, stripped.split_whitespace().collect::<Vec<_>>().join(" "))
+}
+
+// drawtext expands `%{...}` and backslash sequences in its text, whether inline or from a textfile
+pub(crate) fn escape_expansion(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '\\' => escaped.push_str("\\\\"),
+            '%' => escaped.push_str("\\%"),
+            '\r' => {}
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+// Escape a value for use inside single quotes in a filtergraph: the filter option parser
+// needs `\`, `'` and `:` escaped, and the graph-level quotes can only be left and re-entered
+// to insert a literal quote
+pub(crate) fn escape_filter_value(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        match c {
+            '\\' => escaped.push_str("\\\\"),
+            ':' => escaped.push_str("\\:"),
+            '\'' => escaped.push_str("\\'\\''"),
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+// Text for an inline drawtext `text='...'`
+pub(crate) fn escape_drawtext(text: &str) -> String {
+    escape_filter_value(&escape_expansion(text))
+}
+
+pub(crate) fn get_thumbnail_filter(thumbnail: &Thumbnail, dimensions: (u32, u32)) -> String {
+    let (font, text) = select_font(&thumbnail.text, resolve_font(thumbnail.font.as_deref()));
+    let font_color = resolve_color(thumbnail.color.as_deref(), "white");
+    let font_size = thumbnail.fontsize.unwrap_or(DEFAULT_THUMBNAIL_FONT_SIZE);
+    let position = thumbnail.position.as_deref().unwrap_or("center");
+    let y = match position {
+        "top" => "h*0.1",
+        "bottom" => "h*0.9-text_h",
+        _ => "(h-text_h)/2",
+    };
+    let bar = match thumbnail.bar.as_deref() {
+        Some(style) => format!("{},", get_thumbnail_bar(style, position, font_size)),
+        None => String::new(),
+    };
+    
+    // Frame like the video, then Saturation boost + Contrast boost + (bar) + Big Text
+    format!(
+        "{},eq=saturation=1.5:contrast=1.2,{}drawtext=text='{}':fontfile='{}':fontsize={}:fontcolor={}:x=(w-text_w)/2:y={}:borderw=5:bordercolor=black:shadowx=5:shadowy=5",
+        get_cover_filter(dimensions.0, dimensions.1, 0.5), bar, escape_drawtext(&text), escape_filter_value(font), font_size, font_color, y
+    )
+}
+
+pub(crate) const THUMBNAIL_BARS: [&str; 2] = ["solid", "gradient"];
+const THUMBNAIL_BAR_ALPHA: f64 = 0.5;
+const GRADIENT_STEPS: u32 = 6;
+
+// Full-width dark band behind the thumbnail text, sized from the font since drawbox can't
+// see text_h. "gradient" feathers the band's edges with stacked strips of rising opacity.
+fn get_thumbnail_bar(style: &str, position: &str, font_size: u32) -> String {
+    let band = font_size * 8 / 5;
+    let y = match position {
+        "top" => format!("ih*0.1-{}", font_size * 3 / 10),
+        "bottom" => format!("ih*0.9-{}", font_size * 13 / 10),
+        _ => format!("(ih-{})/2", band),
+    };
+    let drawbox = |offset: i64, height: u32, alpha: f64| {
+        format!("drawbox=x=0:y={}{:+}:w=iw:h={}:color=black@{:.2}:t=fill", y, offset, height, alpha)
+    };
+    if style != "gradient" {
+        return drawbox(0, band, THUMBNAIL_BAR_ALPHA);
+    }
+
+    let strip = (band / 2 / GRADIENT_STEPS).max(1);
+    let mut boxes = vec![drawbox(0, band, THUMBNAIL_BAR_ALPHA)];
+    for step in 1..=GRADIENT_STEPS {
+        let alpha = THUMBNAIL_BAR_ALPHA * f64::from(GRADIENT_STEPS + 1 - step) / f64::from(GRADIENT_STEPS + 1);
+        let reach = i64::from(strip * step);
+        boxes.push(drawbox(-reach, strip, alpha));
+        boxes.push(drawbox(i64::from(band) + reach - i64::from(strip), strip, alpha));
+    }
+    boxes.join(",")
+}
+
+pub(crate) const PREVIEW_FORMATS: [&str; 2] = ["gif", "webp"];
+
+// Animated preview: framed like the video, then scaled down and slowed to 12fps to keep the
+// file small. GIF gets a generated palette; the default 256-color one bands badly.
+pub(crate) fn get_preview_filter(format: &str, dimensions: (u32, u32)) -> String {
+    let base = format!("{},fps=12,scale=320:-2:flags=lanczos", get_cover_filter(dimensions.0, dimensions.1, 0.5));
+    match format {
+        "gif" => format!("{},split[pal_src][pal_use];[pal_src]palettegen[pal];[pal_use][pal]paletteuse", base),
+        _ => base,
+    }
+}
+
+const KNOWN_TRANSITIONS: [&str; 12] = [
+    "fade", "wipe", "wipeleft", "wiperight", "wipeup", "wipedown",
+    "slide", "slideleft", "slideright", "slideup", "slidedown", "circleopen",
+];
+// Wipes and slides come in these four directions in xfade
+const DIRECTIONAL_TRANSITIONS: [&str; 2] = ["wipe", "slide"];
+pub(crate) const TRANSITION_DIRECTIONS: [&str; 4] = ["left", "right", "up", "down"];
+
+pub(crate) fn is_known_transition(name: &str) -> bool {
+    KNOWN_TRANSITIONS.contains(&name.to_lowercase().as_str())
+}
+
+pub(crate) fn is_directional_transition(name: &str) -> bool {
+    let name = name.to_lowercase();
+    DIRECTIONAL_TRANSITIONS.iter().any(|family| name.starts_with(family))
+}
+
+// xfade transition name. An explicit direction replaces the one in a wipe/slide name;
+// a bare "wipe" or "slide" defaults to left.
+pub(crate) fn get_transition_filter(name: &str, direction: Option<&str>) -> String {
+    let name = name.to_lowercase();
+    for family in DIRECTIONAL_TRANSITIONS {
+        if let Some(suffix) = name.strip_prefix(family) {
+            let named = Some(suffix).filter(|d| TRANSITION_DIRECTIONS.contains(d));
+            return format!("{}{}", family, direction.or(named).unwrap_or("left"));
+        }
+    }
+    match name.as_str() {
+        "circleopen" => "circleopen",
+        _ => "fade",
+    }
+    .to_string()
+}
+
+pub(crate) fn is_no_color_filter(name: &str) -> bool {
+    let name = name.trim();
+    name.is_empty() || name.eq_ignore_ascii_case("none")
+}
+
+pub(crate) fn get_color_filter(name: &str) -> Option<&'static str> {
+    // Vintage looked wrong on modern content, so it is only applied when asked for verbatim
+    if name == "vintage" {
+        return Some("curves=vintage");
+    }
+    match name.trim().to_lowercase().as_str() {
+        "sepia" => Some("colorchannelmixer=.393:.769:.189:0:.349:.686:.168:0:.272:.534:.131"),
+        "grayscale" => Some("hue=s=0"),
+        "vivid" => Some("eq=saturation=1.5"),
+        "warm" => Some("colorbalance=rs=0.1:gs=0.02:bs=-0.1,eq=saturation=1.1"),
+        "cool" => Some("colorbalance=rs=-0.1:gs=0.0:bs=0.1,eq=saturation=0.95"),
+        _ => None,
+    }
+}
+
+const DEFAULT_CAPTION_BORDER_WIDTH: u32 = 2;
+pub(crate) const CAPTION_POSITIONS: [&str; 3] = ["top", "center", "bottom"];
+pub(crate) const CAPTION_X_PRESETS: [&str; 3] = ["left", "center", "right"];
+
+// A caption coordinate as a share of the frame ("45%") or in pixels ("600" / "600px").
+// None for anything else, including negative values and percentages over 100.
+enum CaptionOffset {
+    Fraction(f64),
+    Pixels(u32),
+}
+
+fn caption_offset(value: &str) -> Option<CaptionOffset> {
+    let value = value.trim();
+    if let Some(percent) = value.strip_suffix('%') {
+        let percent: f64 = percent.trim().parse().ok()?;
+        return (0.0..=100.0).contains(&percent).then(|| CaptionOffset::Fraction(percent / 100.0));
+    }
+    value.strip_suffix("px").unwrap_or(value).trim().parse().ok().map(CaptionOffset::Pixels)
+}
+
+// "45%" -> "{extent}*0.45", "600" / "600px" -> "600"
+pub(crate) fn parse_caption_offset(value: &str, extent: &str) -> Option<String> {
+    Some(match caption_offset(value)? {
+        CaptionOffset::Fraction(fraction) => format!("{}*{}", extent, fraction),
+        CaptionOffset::Pixels(pixels) => pixels.to_string(),
+    })
+}
+
+// The same coordinate in pixels of a frame `extent` pixels wide or tall
+pub(crate) fn resolve_caption_offset(value: &str, extent: u32) -> Option<u32> {
+    Some(match caption_offset(value)? {
+        CaptionOffset::Fraction(fraction) => (extent as f64 * fraction).round() as u32,
+        CaptionOffset::Pixels(pixels) => pixels,
+    })
+}
+
+// drawtext x for a caption line, or `default` when the style has no x. Karaoke layers must
+// share a left edge, so with `fixed_edge` the per-line center/right alignments are ignored.
+pub(crate) fn get_caption_x(style: &Option<CaptionStyle>, default: &str, fixed_edge: bool) -> String {
+    match style.as_ref().and_then(|s| s.x.as_deref()) {
+        Some("left") => "w*0.08".to_string(),
+        Some("center") if !fixed_edge => "(w-text_w)/2".to_string(),
+        Some("right") if !fixed_edge => "w*0.92-text_w".to_string(),
+        Some(x) => parse_caption_offset(x, "w").unwrap_or_else(|| default.to_string()),
+        None => default.to_string(),
+    }
+}
+
+// Caption font, color, box options, outline options and y expression. Captions get a thin
+// black outline by default so white text stays readable on light footage.
+pub(crate) fn get_drawtext_config(style: &Option<CaptionStyle>) -> (String, String, String, String, String) {
+    let default_outline = format!(":borderw={}:bordercolor=black", DEFAULT_CAPTION_BORDER_WIDTH);
+    if let Some(s) = style {
+        let font = resolve_font(s.font.as_deref());
+        
+        let color = resolve_color(s.color.as_deref(), "white");
+        
+        let box_conf = if s.start_box.unwrap_or(false) {
+            ":box=1:boxcolor=black@0.5:boxborderw=5"
+        } else {
+            ""
+        };
+
+        let border_color = resolve_color(s.border_color.as_deref(), "black");
+        let outline = match s.border_width.unwrap_or(DEFAULT_CAPTION_BORDER_WIDTH) {
+            0 => String::new(),
+            width => format!(":borderw={}:bordercolor={}", width, border_color),
+        };
+        
+        let y = match s.position.as_deref().unwrap_or("bottom") {
+            "top" => "h*0.1".to_string(),
+            "center" => "(h-text_h)/2".to_string(),
+            position => parse_caption_offset(position, "h").unwrap_or_else(|| "h*0.85".to_string()), // Safer bottom for vertical video UI
+        };
+        
+        (font.to_string(), color, box_conf.to_string(), outline, y)
+    } else {
+        (SANS_FONT.to_string(), "white".to_string(), "".to_string(), default_outline, "h*0.85".to_string())
+    }
+}
+
+// Overlay a caption ribbon/banner image behind the caption line, centered horizontally and
+// vertically on the first line. `movie` keeps this inside the simple -vf graph; a still
+// image's single frame is repeated by overlay for the whole segment.
+pub(crate) fn get_caption_background_filter(asset: &Path, style: &Option<CaptionStyle>, font_size: u32) -> String {
+    let y = match style.as_ref().and_then(|s| s.position.as_deref()).unwrap_or("bottom") {
+        "top" => "H*0.1".to_string(),
+        "center" => format!("(H-{})/2", font_size),
+        position => parse_caption_offset(position, "H").unwrap_or_else(|| "H*0.85".to_string()),
+    };
+    format!(
+        "null[cap_base];movie='{}'[cap_bg];[cap_base][cap_bg]overlay=x=(W-w)/2:y={}+{}-h/2",
+        escape_filter_value(&asset.to_string_lossy()), y, font_size / 2
+    )
+}
+
+// Burn an .ass file in with libass; fontsdir lets it find fonts outside fontconfig's paths
+pub(crate) fn get_subtitles_filter(path: &Path, fonts_dir: &str) -> String {
+    format!(
+        "subtitles=filename='{}':fontsdir='{}'",
+        escape_filter_value(&path.to_string_lossy()), escape_filter_value(fonts_dir)
+    )
+}
+
+// Hold the first/last frame for `start`/`end` seconds around `content` seconds of video, as
+// (video, audio) filter chains. The input runs on past the cut and tpad only pads once its
+// input ends, so both streams are trimmed to the content length first; audio gets silence.
+pub(crate) fn get_freeze_filters(content: f64, start: f64, end: f64) -> (String, String) {
+    (
+        format!(
+            "trim=duration={:.3},setpts=PTS-STARTPTS,tpad=start_mode=clone:start_duration={:.3}:stop_mode=clone:stop_duration={:.3}",
+            content, start, end
+        ),
+        format!(
+            "atrim=duration={:.3},asetpts=PTS-STARTPTS,adelay={}:all=1,apad=pad_dur={:.3}",
+            content, (start * 1000.0).round() as u64, end
+        ),
+    )
+}
+
+pub(crate) const WATERMARK_POSITIONS: [&str; 4] = ["top_left", "top_right", "bottom_left", "bottom_right"];
+
+// Color grade from a 3D LUT file, interpolated tetrahedrally like most grading tools
+pub(crate) fn get_lut_filter(lut: &Path) -> String {
+    format!("lut3d=file='{}':interp=tetrahedral", escape_filter_value(&lut.to_string_lossy()))
+}
+
+// Corner logo scaled to 15% of the frame width, inset 40px. Applied per segment rather than
+// once on the final video: segments are re-encoded anyway, so the final concat can keep
+// `-c:v copy`, and a logo in the same spot on both sides of a transition stays put.
+pub(crate) fn get_watermark_filter(asset: &Path, position: &str, opacity: f64, width: u32) -> String {
+    let (x, y) = match position {
+        "top_left" => ("40", "40"),
+        "bottom_left" => ("40", "H-h-40"),
+        "bottom_right" => ("W-w-40", "H-h-40"),
+        _ => ("W-w-40", "40"),
+    };
+    format!(
+        "null[wm_base];movie='{}',scale={}:-1,format=rgba,colorchannelmixer=aa={:.2}[wm];[wm_base][wm]overlay=x={}:y={}",
+        escape_filter_value(&asset.to_string_lossy()), width * 15 / 100, opacity.clamp(0.0, 1.0), x, y
+    )
+}
+
+pub(crate) const WAVEFORM_STYLES: [&str; 2] = ["waves", "cqt"];
+pub(crate) const WAVEFORM_POSITIONS: [&str; 3] = ["top", "center", "bottom"];
+const WAVEFORM_FPS: u32 = 30;
+
+// Audio visualizer for the final mix and the overlay that places it, full width and `height`
+// pixels tall. showwaves draws on a transparent frame; showcqt's black background is keyed
+// out. "bottom" sits above the default caption line at h*0.85.
+pub(crate) fn get_waveform_filters(style: &str, position: &str, width: u32, height: u32) -> (String, String) {
+    let visualizer = match style {
+        "cqt" => format!(
+            "showcqt=s={}x{}:r={}:axis_h=0:sono_h=0,format=rgba,colorkey=black:0.1",
+            width, height, WAVEFORM_FPS
+        ),
+        _ => format!("showwaves=s={}x{}:mode=cline:rate={}:colors=white", width, height, WAVEFORM_FPS),
+    };
+    let y = match position {
+        "top" => "H*0.1",
+        "center" => "(H-h)/2",
+        _ => "H*0.8-h",
+    };
+    (visualizer, format!("overlay=x=0:y={}", y))
+}
+
+// Drops the given [from, to) stretches (on the input clock) from a clip and closes the gaps,
+// as (video, audio) filters to run first in each chain
+pub(crate) fn get_silence_trim_filters(silences: &[(f64, f64)]) -> (String, String) {
+    let dropped: Vec<String> = silences.iter().map(|(from, to)| format!("between(t,{:.3},{:.3})", from, to)).collect();
+    let keep = format!("not({})", dropped.join("+"));
+    (
+        format!("select='{}',setpts=N/FRAME_RATE/TB", keep),
+        format!("aselect='{}',asetpts=N/SR/TB", keep),
+    )
+}
+
+pub(crate) const PROGRESS_BAR_POSITIONS: [&str; 2] = ["top", "bottom"];
+
+// Progress bar over the final video: a solid strip slid in from the left so its visible part
+// is t/duration of the width (drawbox can't read the frame time). Returns the color source
+// and the overlay that places it; the source ends with the video and the overlay then holds
+// its last, full-width frame.
+pub(crate) fn get_progress_bar_filters(position: &str, color: &str, width: u32, height: u32, duration: f64) -> (String, String) {
+    let thickness = (height / 120).max(2);
+    let source = format!("color=c={}:s={}x{}:r={}:d={:.3}", color, width, thickness, WAVEFORM_FPS, duration);
+    let y = if position == "top" { "0" } else { "H-h" };
+    (source, format!("overlay=x='-w+W*t/{:.3}':y={}:eval=frame", duration.max(0.001), y))
+}
+
+// Greedy word wrap. Words longer than the limit (e.g. unspaced CJK text) are split by
+// character; explicit newlines in the caption always break.
+pub(crate) fn wrap_caption(text: &str, max_chars: usize) -> Vec<String> {
+    let max_chars = max_chars.max(1);
+    let mut lines = Vec::new();
+
+    for paragraph in text.lines() {
+        let mut current = String::new();
+        for word in paragraph.split_whitespace() {
+            let chars: Vec<char> = word.chars().collect();
+            for chunk in chars.chunks(max_chars) {
+                let chunk: String = chunk.iter().collect();
+                let current_len = current.chars().count();
+                if current_len > 0 && current_len + 1 + chunk.chars().count() > max_chars {
+                    lines.push(std::mem::take(&mut current));
+                }
+                if !current.is_empty() {
+                    current.push(' ');
+                }
+                current.push_str(&chunk);
+            }
+        }
+        if !current.is_empty() {
+            lines.push(current);
+        }
+    }
+
+    lines
+}
+
+// y expression for one line of a stacked caption. The block grows away from its anchor:
+// down from the top or an explicit coordinate, up from the bottom, and evenly around the center.
+pub(crate) fn get_caption_line_y(base_y: &str, style: &Option<CaptionStyle>, line: usize, lines: usize, line_height: u32) -> String {
+    if lines <= 1 {
+        return base_y.to_string();
+    }
+    let position = style.as_ref().and_then(|s| s.position.as_deref()).unwrap_or("bottom");
+    let from_top = position == "top" || parse_caption_offset(position, "h").is_some();
+    let offset = match position {
+        _ if from_top => line as f64,
+        "center" => line as f64 - (lines - 1) as f64 / 2.0,
+        _ => line as f64 - (lines - 1) as f64,
+    } * line_height as f64;
+
+    if offset == 0.0 {
+        base_y.to_string()
+    } else {
+        format!("{}+({})", base_y, offset)
+    }
+}
+
+pub(crate) const CAPTION_ANIMATIONS: [&str; 2] = ["slide_up", "slide_left"];
+const CAPTION_SLIDE_DURATION: f64 = 0.4;
+
+// drawtext x/y for a caption resting at (x, y). slide_up rises from the bottom edge and
+// slide_left comes in from the right edge, easing out over the first 0.4s of the segment.
+pub(crate) fn get_caption_motion(style: &Option<CaptionStyle>, x: &str, y: &str) -> (String, String) {
+    // Share of the distance still to travel: 1 at t=0, 0 once the slide is over
+    let remaining = format!("pow(1-min(t/{:.3},1),2)", CAPTION_SLIDE_DURATION);
+    match style.as_ref().and_then(|s| s.animation.as_deref()) {
+        Some("slide_up") => (x.to_string(), format!("'({y})+(h-({y}))*{remaining}'")),
+        Some("slide_left") => (format!("'({x})+(w-({x}))*{remaining}'"), y.to_string()),
+        _ => (x.to_string(), y.to_string()),
+    }
+}
+
+// drawtext alpha option ramping 0->1 over fade_in and 1->0 over the last fade_out seconds
+// of a caption shown from t=0 to `duration`. None when neither fade is set.
+pub(crate) fn get_caption_alpha(style: &Option<CaptionStyle>, duration: f64) -> Option<String> {
+    let style = style.as_ref()?;
+    let fade_in = style.fade_in.filter(|d| *d > 0.0);
+    let fade_out = style.fade_out.filter(|d| *d > 0.0);
+    if fade_in.is_none() && fade_out.is_none() {
+        return None;
+    }
+
+    let mut expr = "1".to_string();
+    if let Some(out) = fade_out {
+        expr = format!("if(gt(t,{:.3}),({:.3}-t)/{:.3},{})", duration - out, duration, out, expr);
+    }
+    if let Some(fade) = fade_in {
+        expr = format!("if(lt(t,{:.3}),t/{:.3},{})", fade, fade, expr);
+    }
+    Some(format!(":alpha='{}'", expr))
+}
+
+// Word indices grouped into lines of at most max_chars characters
+pub(crate) fn group_karaoke_lines(words: &[(String, f64)], max_chars: usize) -> Vec<Vec<usize>> {
+    let mut lines: Vec<Vec<usize>> = Vec::new();
+    let mut line_len = 0;
+    for (i, (word, _)) in words.iter().enumerate() {
+        let len = word.chars().count();
+        match lines.last_mut() {
+            Some(line) if line_len + 1 + len <= max_chars => {
+                line.push(i);
+                line_len += 1 + len;
+            }
+            _ => {
+                lines.push(vec![i]);
+                line_len = len;
+            }
+        }
+    }
+    lines
+}
+
+// Word-by-word reveal. drawtext can't color part of a string, so for each active word the
+// line's revealed prefix is drawn in the highlight color and the prefix without the active
+// word is overdrawn in the base color. Both layers share a fixed left edge so the glyphs line
+// up, which means karaoke lines are left-aligned rather than centered.
+pub(crate) fn get_karaoke_filters(
+    words: &[(String, f64)],
+    style: &Option<CaptionStyle>,
+    max_chars: usize,
+    font_size: u32,
+    line_height: u32,
+    duration: f64,
+    text_arg: &mut dyn FnMut(&str) -> String,
+) -> Vec<String> {
+    let (font, color, box_conf, outline, y) = get_drawtext_config(style);
+    let highlight = resolve_color(style.as_ref().and_then(|s| s.highlight_color.as_deref()), "yellow");
+
+    let lines = group_karaoke_lines(words, max_chars);
+
+    let mut drawtext = |text: &str, fontcolor: &str, line: usize, boxed: bool, from: f64, to: f64| {
+        let (font, text) = select_font(text, &font);
+        let line_y = get_caption_line_y(&y, style, line, lines.len(), line_height);
+        let (x, line_y) = get_caption_motion(style, &get_caption_x(style, "w*0.08", true), &line_y);
+        format!(
+            "drawtext=fontfile='{}':{}:fontcolor={}:fontsize={}:x={}:y={}{}{}:enable='between(t,{:.3},{:.3})'",
+            escape_filter_value(font), text_arg(&text), fontcolor, font_size, x, line_y, outline,
+            if boxed { box_conf.as_str() } else { "" }, from, to
+        )
+    };
+    let joined = |indices: &[usize]| indices.iter().map(|&i| words[i].0.as_str()).collect::<Vec<_>>().join(" ");
+
+    let mut filters = Vec::new();
+    for (line, indices) in lines.iter().enumerate() {
+        for (pos, &i) in indices.iter().enumerate() {
+            let from = words[i].1;
+            let to = words.get(i + 1).map(|(_, t)| *t).unwrap_or(duration);
+            filters.push(drawtext(&joined(&indices[..=pos]), &highlight, line, true, from, to));
+            if pos > 0 {
+                filters.push(drawtext(&joined(&indices[..pos]), &color, line, false, from, to));
+            }
+        }
+        // Finished lines stay on screen in the base color until the segment ends
+        let last = *indices.last().unwrap();
+        if let Some((_, next_start)) = words.get(last + 1) {
+            filters.push(drawtext(&joined(indices), &color, line, true, *next_start, duration));
+        }
+    }
+
+    filters
+}
+
+// Oscillating crop for a camera shake between `from` and `to` seconds. The frame is cropped
+// by a small margin for the whole segment (crop size can't change per frame) and the offset
+// wanders inside that margin, so the shake never exposes black edges.
+pub(crate) fn get_shake_filter(width: u32, height: u32, amount: f64, from: f64, to: f64) -> String {
+    let zoom = 1.0 + 0.08 * amount.clamp(0.0, 1.0);
+    let active = format!("between(t,{:.3},{:.3})", from, to);
+    format!(
+        "crop=iw/{z:.3}:ih/{z:.3}:x='(iw-ow)/2*(1+sin(t*41)*{a})':y='(ih-oh)/2*(1+cos(t*37)*{a})',scale={w}:{h}",
+        z = zoom, a = active, w = width, h = height
+    )
+}
+
+// Playback rate for a speed effect: explicit `speed` wins, otherwise 0.5 for slowmo and
+// 2.0 for speedup. None for effect types that don't change timing.
+pub(crate) fn get_speed_factor(effect_type: &str, speed: Option<&str>) -> Option<f64> {
+    let default = match effect_type {
+        "slowmo" => 0.5,
+        "speedup" => 2.0,
+        _ => return None,
+    };
+    let factor = speed.and_then(|s| s.trim().parse::<f64>().ok()).unwrap_or(default);
+    (factor > 0.0).then_some(factor)
+}
+
+// atempo only accepts 0.5..2.0 per instance, so larger changes are chained
+pub(crate) fn get_atempo_chain(speed: f64) -> String {
+    let mut remaining = speed;
+    let mut stages = Vec::new();
+    while remaining > 2.0 {
+        stages.push("atempo=2.0".to_string());
+        remaining /= 2.0;
+    }
+    while remaining < 0.5 {
+        stages.push("atempo=0.5".to_string());
+        remaining /= 0.5;
+    }
+    stages.push(format!("atempo={}", remaining));
+    stages.join(",")
+}
+
+// Map an SE pan setting to a mono-to-stereo pan filter (balance law: center keeps both at 1.0)
+pub(crate) fn get_pan_filter(pan: &str) -> Option<String> {
+    let balance = match pan.trim().to_lowercase().as_str() {
+        "left" => -1.0,
+        "right" => 1.0,
+        "center" | "centre" => 0.0,
+        other => other.parse::<f64>().ok()?.clamp(-1.0, 1.0),
+    };
+    let left_gain = (1.0 - balance).min(1.0);
+    let right_gain = (1.0 + balance).min(1.0);
+    Some(format!("aformat=channel_layouts=mono,pan=stereo|c0={:.3}*c0|c1={:.3}*c0", left_gain, right_gain))
+}
+
+// BGM dip around each SE: down by SE_DUCK_DEPTH over SE_DUCK_ATTACK before the hit, held for
+// SE_DUCK_HOLD, back up over SE_DUCK_RELEASE. Overlapping dips multiply.
+const SE_DUCK_DEPTH: f64 = 0.6;
+const SE_DUCK_ATTACK: f64 = 0.05;
+const SE_DUCK_HOLD: f64 = 0.3;
+const SE_DUCK_RELEASE: f64 = 0.3;
+
+pub(crate) fn get_se_duck_filter(times: &[f64]) -> String {
+    let dips: Vec<String> = times.iter().map(|&t| {
+        format!(
+            "(1-{}*clip(min((t-{:.3})/{},({:.3}-t)/{}),0,1))",
+            SE_DUCK_DEPTH, t - SE_DUCK_ATTACK, SE_DUCK_ATTACK, t + SE_DUCK_HOLD + SE_DUCK_RELEASE, SE_DUCK_RELEASE
+        )
+    }).collect();
+    format!("volume='{}':eval=frame", dips.join("*"))
+}
+
+// Single-pass EBU R128 normalization. loudnorm upsamples internally, so resample back down.
+pub(crate) fn get_loudnorm_filter(target_lufs: f64) -> String {
+    format!("loudnorm=I={}:TP=-1.5:LRA=11,aresample=48000", target_lufs)
+}
+
+// Brickwall ceiling at -1 dBFS for the mixed voice/BGM/SE. level=0 stops alimiter from
+// also auto-raising quiet output to the ceiling.
+pub(crate) fn get_limiter_filter() -> &'static str {
+    "alimiter=limit=0.891:attack=5:release=50:level=0"
+}
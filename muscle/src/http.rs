@@ -0,0 +1,91 @@
+use anyhow::Result;
+use muscle::{job_scope, log_error, log_json, new_job_id, process_instruction, validate, Config};
+use serde_json::json;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use tiny_http::{Header, Method, Request, Response, Server};
+
+use crate::status::Status;
+
+// Optional HTTP trigger: POST /process with an Analysis JSON body renders it synchronously.
+// GET /healthz reports uptime and counters; with the metrics feature, GET /metrics serves
+// the same counters for Prometheus. Once shutdown starts, new renders get 503.
+// Each request gets its own thread so health checks still answer during a render;
+// render_lock keeps renders one at a time.
+pub fn spawn(addr: &str, config: Config, render_lock: Arc<Mutex<()>>, status: Arc<Status>, shutdown: Arc<AtomicBool>) -> Result<()> {
+    let server = Server::http(addr).map_err(|e| anyhow::anyhow!("HTTP bind to {} failed: {}", addr, e))?;
+    let config = Arc::new(config);
+
+    std::thread::spawn(move || {
+        for request in server.incoming_requests() {
+            let (config, render_lock, status, shutdown) =
+                (Arc::clone(&config), Arc::clone(&render_lock), Arc::clone(&status), Arc::clone(&shutdown));
+            std::thread::spawn(move || handle(request, &config, &render_lock, &status, &shutdown));
+        }
+    });
+
+    Ok(())
+}
+
+fn handle(mut request: Request, config: &Config, render_lock: &Mutex<()>, status: &Status, shutdown: &AtomicBool) {
+    #[cfg(feature = "metrics")]
+    if request.method() == &Method::Get && request.url() == "/metrics" {
+        respond(request, 200, "text/plain; version=0.0.4", status.to_prometheus());
+        return;
+    }
+
+    let (code, body) = match (request.method(), request.url()) {
+        (Method::Post, "/process") if shutdown.load(Ordering::SeqCst) => (503, json!({ "error": "Shutting down" })),
+        (Method::Post, "/process") => {
+            status.record_seen();
+            let mut content = String::new();
+            let (code, body) = match request.as_reader().read_to_string(&mut content) {
+                Ok(_) => process_body(&content, config, render_lock),
+                Err(e) => (400, json!({ "error": format!("Failed to read body: {}", e) })),
+            };
+            status.record_result(code == 200);
+            (code, body)
+        }
+        (Method::Get, "/healthz") => (200, status.to_json()),
+        _ => (404, json!({ "error": "Not found" })),
+    };
+
+    respond(request, code, "application/json", body.to_string());
+}
+
+fn respond(request: Request, code: u16, content_type: &str, body: String) {
+    let response = Response::from_string(body)
+        .with_status_code(code)
+        .with_header(Header::from_bytes(&b"Content-Type"[..], content_type.as_bytes()).unwrap());
+    if let Err(e) = request.respond(response) {
+        log_json("ERROR", &format!("HTTP response failed: {}", e), Some("http_error"), None);
+    }
+}
+
+fn process_body(content: &str, config: &Config, render_lock: &Mutex<()>) -> (u16, serde_json::Value) {
+    let job = new_job_id(None);
+    let _job = job_scope(&job);
+    let analysis = match config.parse_analysis(content) {
+        Ok(analysis) => analysis,
+        Err(e) => {
+            log_json("ERROR", &format!("JSON parse failed: {}", e), Some("parse_error"), None);
+            return (400, json!({ "error": format!("JSON parse failed: {}", e) }));
+        }
+    };
+
+    if let Err(problems) = validate(&analysis) {
+        log_json("ERROR", &format!("Validation failed: {}", problems.join("; ")), Some("validation_error"), None);
+        return (422, json!({ "error": "Validation failed", "problems": problems }));
+    }
+
+    log_json("INFO", &format!("HTTP render requested for {}", analysis.original_filename), Some("http_request"), None);
+
+    let _guard = render_lock.lock().unwrap_or_else(|e| e.into_inner());
+    match process_instruction(&analysis, config) {
+        Ok(output_path) => (200, json!({ "output": output_path, "job": job })),
+        Err(e) => {
+            log_error(&format!("Processing failed: {}", e), Some("process_error"), None, &e);
+            (500, json!({ "error": e.to_string(), "job": job }))
+        }
+    }
+}
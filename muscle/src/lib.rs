@@ -0,0 +1,31 @@
+// Core rendering pipeline for nue: turns an Analysis (cuts, captions, SE, BGM)
+// into a finished video with ffmpeg. The `muscle` binary is a thin file watcher on top.
+
+mod analysis;
+mod ass;
+mod command;
+mod config;
+mod ffmpeg;
+mod filters;
+mod logging;
+mod manifest;
+mod probe;
+mod process;
+mod se;
+mod subtitles;
+mod temp;
+mod time;
+mod upload;
+mod validate;
+mod webhook;
+
+pub use analysis::{Analysis, BgmSegment, CaptionStyle, Cut, SeEvent, Thumbnail, TimeFormat, VisualEffect};
+pub use config::Config;
+pub use ffmpeg::{ffmpeg_seconds, FfmpegError, VideoEncoder};
+pub use logging::{init_logging, job_scope, log_error, log_json, new_job_id, JobScope};
+pub use probe::SourceInfo;
+pub use process::{abort_renders, generate_preview, generate_thumbnail, process_instruction, process_single_segment};
+pub use se::{SeMap, SeRule};
+pub use time::parse_time;
+pub use upload::S3Config;
+pub use validate::validate;
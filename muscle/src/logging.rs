@@ -0,0 +1,270 @@
+use log::{Level, LevelFilter};
+use rand::Rng;
+use serde::Serialize;
+use std::cell::RefCell;
+use std::io::Write;
+use std::path::Path;
+use std::process::Command;
+use std::sync::OnceLock;
+
+use crate::ffmpeg::FfmpegError;
+use crate::time::reset_ambiguous_warnings;
+
+// Target for records that are already rendered entries, as opposed to plain log macros
+const TARGET: &str = "nue";
+
+#[derive(Clone, Copy, PartialEq)]
+enum LogFormat {
+    Json,
+    Text,
+}
+
+// Unset until init_logging runs; entries are then printed as JSON directly, so library
+// users that never set up a logger still see them
+static FORMAT: OnceLock<LogFormat> = OnceLock::new();
+
+// The single log sink on stdout, configured by NUE_LOG_FORMAT ("json" default, or "text")
+// and NUE_LOG_LEVEL ("error", "warn", "info" default, "debug"). Entries from log_json and
+// friends and the `log` macros all go through it and honor the level.
+pub fn init_logging() {
+    let format_var = std::env::var("NUE_LOG_FORMAT").ok();
+    let format = match format_var.as_deref().map(|v| v.trim().to_lowercase()).as_deref() {
+        Some("text") => LogFormat::Text,
+        _ => LogFormat::Json,
+    };
+    let level_var = std::env::var("NUE_LOG_LEVEL").ok();
+    let level = level_var.as_deref().and_then(|v| v.trim().parse::<LevelFilter>().ok());
+    let _ = FORMAT.set(format);
+
+    env_logger::Builder::new()
+        .filter_level(level.unwrap_or(LevelFilter::Info))
+        .target(env_logger::Target::Stdout)
+        .format(|buf, record| {
+            if record.target() == TARGET {
+                return writeln!(buf, "{}", record.args());
+            }
+            // Plain log macros get the same shape as our own entries
+            let message = record.args().to_string();
+            let entry = LogEntry {
+                severity: severity(record.level()),
+                message: &message,
+                event: None,
+                path: None,
+                job: current_job(),
+                exit_code: None,
+                stderr_tail: None,
+            };
+            writeln!(buf, "{}", render(&entry).unwrap_or(message.clone()))
+        })
+        .init();
+
+    if format_var.is_some_and(|v| !["json", "text"].contains(&v.trim().to_lowercase().as_str())) {
+        log_json("WARN", "Unknown NUE_LOG_FORMAT, using json", Some("config"), None);
+    }
+    if level_var.is_some() && level.is_none() {
+        log_json("WARN", "Unknown NUE_LOG_LEVEL, using info", Some("config"), None);
+    }
+}
+
+thread_local! {
+    // Job whose work this thread is doing; renders run on one thread, so entries from
+    // process_single_segment, generate_thumbnail etc. pick it up without passing it around
+    static JOB: RefCell<Option<String>> = const { RefCell::new(None) };
+}
+
+// Tags every entry logged on this thread with the job ID until dropped, then restores the
+// outer job (if any). A new job also gets its ambiguous timestamps flagged afresh.
+pub struct JobScope {
+    previous: Option<String>,
+}
+
+pub fn job_scope(job: &str) -> JobScope {
+    reset_ambiguous_warnings();
+    let previous = JOB.with(|current| current.replace(Some(job.to_string())));
+    JobScope { previous }
+}
+
+impl Drop for JobScope {
+    fn drop(&mut self) {
+        JOB.with(|current| *current.borrow_mut() = self.previous.take());
+    }
+}
+
+pub(crate) fn current_job() -> Option<String> {
+    JOB.with(|current| current.borrow().clone())
+}
+
+// The instruction file's stem, or a random "job-xxxxxxxx" for instructions without a file
+// (e.g. HTTP requests)
+pub fn new_job_id(instruction: Option<&Path>) -> String {
+    instruction
+        .and_then(|path| path.file_stem())
+        .map(|stem| stem.to_string_lossy().into_owned())
+        .unwrap_or_else(|| format!("job-{:08x}", rand::thread_rng().gen::<u32>()))
+}
+
+fn severity(level: Level) -> &'static str {
+    match level {
+        Level::Error => "ERROR",
+        Level::Warn => "WARN",
+        Level::Info => "INFO",
+        Level::Debug | Level::Trace => "DEBUG",
+    }
+}
+
+fn level(severity: &str) -> Level {
+    match severity {
+        "ERROR" => Level::Error,
+        "WARN" => Level::Warn,
+        "DEBUG" => Level::Debug,
+        _ => Level::Info,
+    }
+}
+
+// An entry as one line: JSON, or "LEVEL [event] message" followed by its other fields as
+// key=value (argv is left out, the dry-run message already holds the command line)
+fn render<T: Serialize>(entry: &T) -> Option<String> {
+    if FORMAT.get() != Some(&LogFormat::Text) {
+        return serde_json::to_string(entry).ok();
+    }
+    let value = serde_json::to_value(entry).ok()?;
+    let fields = value.as_object()?;
+    let text = |key: &str| fields.get(key).and_then(|v| v.as_str()).unwrap_or("");
+    let mut line = format!("{:<5}", text("severity"));
+    if let Some(event) = fields.get("event").and_then(|v| v.as_str()) {
+        line.push_str(&format!(" [{}]", event));
+    }
+    line.push_str(&format!(" {}", text("message")));
+    for (key, value) in fields {
+        if matches!(key.as_str(), "severity" | "event" | "message" | "argv") || value.is_null() {
+            continue;
+        }
+        match value.as_str() {
+            Some(s) => line.push_str(&format!(" {}={:?}", key, s)),
+            None => line.push_str(&format!(" {}={}", key, value)),
+        }
+    }
+    Some(line)
+}
+
+fn emit<T: Serialize>(severity: &str, entry: &T) {
+    let level = level(severity);
+    if FORMAT.get().is_some() && !log::log_enabled!(target: TARGET, level) {
+        return;
+    }
+    let Some(line) = render(entry) else {
+        return;
+    };
+    if FORMAT.get().is_some() {
+        log::log!(target: TARGET, level, "{}", line);
+    } else {
+        println!("{}", line);
+    }
+}
+
+#[derive(Serialize)]
+struct LogEntry<'a> {
+    severity: &'a str,
+    message: &'a str,
+    event: Option<&'a str>,
+    path: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    job: Option<String>,
+    // Set for ffmpeg failures only
+    #[serde(skip_serializing_if = "Option::is_none")]
+    exit_code: Option<i32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    stderr_tail: Option<String>,
+}
+
+#[derive(Serialize)]
+struct CommandLogEntry<'a> {
+    severity: &'a str,
+    message: &'a str,
+    event: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    job: Option<String>,
+    argv: Vec<String>,
+}
+
+#[derive(Serialize)]
+struct ProgressLogEntry<'a> {
+    severity: &'a str,
+    message: &'a str,
+    event: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    job: Option<String>,
+    label: &'a str,
+    percent: f64,
+    frame: u64,
+    out_time: f64,
+}
+
+fn shell_quote(arg: &str) -> String {
+    let is_plain = !arg.is_empty()
+        && arg.chars().all(|c| c.is_ascii_alphanumeric() || "-_./=:@%+,".contains(c));
+    if is_plain {
+        arg.to_string()
+    } else {
+        format!("'{}'", arg.replace('\'', "'\\''"))
+    }
+}
+
+// Log a command instead of running it. The message is a shell-ready command line.
+pub(crate) fn log_dry_run(cmd: &Command, what: &str) {
+    let mut argv = vec![cmd.get_program().to_string_lossy().into_owned()];
+    argv.extend(cmd.get_args().map(|arg| arg.to_string_lossy().into_owned()));
+    let command_line = argv.iter().map(|arg| shell_quote(arg)).collect::<Vec<_>>().join(" ");
+    let message = format!("[dry-run] {}: {}", what, command_line);
+    let entry = CommandLogEntry {
+        severity: "INFO",
+        message: &message,
+        event: "dry_run",
+        job: current_job(),
+        argv,
+    };
+    emit(entry.severity, &entry);
+}
+
+pub fn log_json(level: &str, message: &str, event: Option<&str>, path: Option<&str>) {
+    let entry = LogEntry {
+        severity: level,
+        message,
+        event,
+        path,
+        job: current_job(),
+        exit_code: None,
+        stderr_tail: None,
+    };
+    emit(entry.severity, &entry);
+}
+
+// ERROR entry for a failure; if it came from ffmpeg, the exit code and stderr tail ride along
+pub fn log_error(message: &str, event: Option<&str>, path: Option<&str>, error: &anyhow::Error) {
+    let ffmpeg = error.downcast_ref::<FfmpegError>();
+    let entry = LogEntry {
+        severity: "ERROR",
+        message,
+        event,
+        path,
+        job: current_job(),
+        exit_code: ffmpeg.and_then(|e| e.exit_code),
+        stderr_tail: ffmpeg.map(|e| e.stderr_tail()),
+    };
+    emit(entry.severity, &entry);
+}
+
+pub(crate) fn log_progress(label: &str, percent: f64, frame: u64, out_time: f64) {
+    let message = format!("{}: {:.1}% (frame {}, {:.2}s)", label, percent, frame, out_time);
+    let entry = ProgressLogEntry {
+        severity: "INFO",
+        message: &message,
+        event: "progress",
+        job: current_job(),
+        label,
+        percent: (percent * 10.0).round() / 10.0,
+        frame,
+        out_time,
+    };
+    emit(entry.severity, &entry);
+}
@@ -0,0 +1,52 @@
+use anyhow::Result;
+use serde::Serialize;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+#[derive(Serialize)]
+pub(crate) struct SeUsage {
+    pub timestamp: String,
+    pub file: PathBuf,
+}
+
+// Machine-readable summary of a render, written next to the output as
+// {filename}.result.json. Filled in as processing proceeds so a failed run still
+// records how far it got.
+#[derive(Serialize, Default)]
+pub(crate) struct RenderManifest {
+    pub success: bool,
+    // The output was already newer than its inputs, so nothing was rendered this time
+    pub skipped: bool,
+    pub output: Option<PathBuf>,
+    pub thumbnails: Vec<PathBuf>,
+    // Animated GIF/WebP thumbnail previews
+    pub previews: Vec<PathBuf>,
+    pub subtitles: Vec<PathBuf>,
+    // URLs of files uploaded to S3-compatible storage, if configured
+    pub uploads: Vec<String>,
+    pub total_duration: f64,
+    pub segments: usize,
+    // Cut indices dropped under continue_on_error
+    pub skipped_segments: Vec<usize>,
+    pub bgm: Option<PathBuf>,
+    // Files used by bgm_segments, in order (bgm stays empty then)
+    pub bgm_segments: Vec<PathBuf>,
+    pub se: Vec<SeUsage>,
+    // True peak of the final mix in dBFS, when check_clipping measured it
+    pub peak_dbfs: Option<f64>,
+    pub clipped: Option<bool>,
+    // Final file size, when max_output_bytes was checked
+    pub output_bytes: Option<u64>,
+    pub error: Option<String>,
+}
+
+impl RenderManifest {
+    pub fn path_for(output_dir: &Path, filename: &str) -> PathBuf {
+        output_dir.join(format!("{}.result.json", filename))
+    }
+
+    pub fn write(&self, path: &Path) -> Result<()> {
+        fs::write(path, serde_json::to_string_pretty(self)?)?;
+        Ok(())
+    }
+}
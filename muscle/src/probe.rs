@@ -0,0 +1,220 @@
+use anyhow::Result;
+use serde::Deserialize;
+use std::path::Path;
+use std::process::Command;
+
+use crate::command::ffmpeg_command;
+use crate::ffmpeg::run;
+use crate::logging::log_json;
+
+// What ffprobe tells us about the raw input, gathered once per render
+#[derive(Debug, Clone)]
+pub struct SourceInfo {
+    pub has_audio: bool,
+    // Width x height of the first video stream, if ffprobe could read it
+    pub dimensions: Option<(u32, u32)>,
+    // Container duration in seconds
+    pub duration: Option<f64>,
+    pub fps: Option<f64>,
+    pub video_codec: Option<String>,
+    pub audio_codec: Option<String>,
+}
+
+impl Default for SourceInfo {
+    // Assume a normal clip when probing isn't possible
+    fn default() -> Self {
+        SourceInfo { has_audio: true, dimensions: None, duration: None, fps: None, video_codec: None, audio_codec: None }
+    }
+}
+
+#[derive(Deserialize)]
+struct ProbeOutput {
+    #[serde(default)]
+    streams: Vec<ProbeStream>,
+    format: Option<ProbeFormat>,
+}
+
+#[derive(Deserialize)]
+struct ProbeStream {
+    codec_type: Option<String>,
+    codec_name: Option<String>,
+    r_frame_rate: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct ProbeFormat {
+    duration: Option<String>,
+}
+
+pub(crate) fn probe_source(path: &Path) -> SourceInfo {
+    let mut info = SourceInfo::default();
+    match check_audio_stream(path) {
+        Ok(has_audio) => info.has_audio = has_audio,
+        Err(e) => log_json("WARN", &format!("ffprobe failed, assuming audio is present: {}", e), Some("probe_failed"), path.to_str()),
+    }
+    match probe_dimensions(path) {
+        Ok(dimensions) => info.dimensions = dimensions,
+        Err(e) => log_json("WARN", &format!("ffprobe could not read dimensions: {}", e), Some("probe_failed"), path.to_str()),
+    }
+    match probe_metadata(path) {
+        Ok(metadata) => {
+            info.duration = metadata.format.and_then(|f| f.duration?.parse().ok());
+            for stream in metadata.streams {
+                match stream.codec_type.as_deref() {
+                    Some("video") if info.video_codec.is_none() => {
+                        info.video_codec = stream.codec_name;
+                        info.fps = stream.r_frame_rate.as_deref().and_then(parse_frame_rate);
+                    }
+                    Some("audio") if info.audio_codec.is_none() => info.audio_codec = stream.codec_name,
+                    _ => {}
+                }
+            }
+        }
+        Err(e) => log_json("WARN", &format!("ffprobe could not read metadata: {}", e), Some("probe_failed"), path.to_str()),
+    }
+
+    let unknown = || "unknown".to_string();
+    log_json("INFO", &format!(
+        "Source probe: audio={} dimensions={} duration={} fps={} video_codec={} audio_codec={}",
+        info.has_audio,
+        info.dimensions.map(|(w, h)| format!("{}x{}", w, h)).unwrap_or_else(unknown),
+        info.duration.map(|d| format!("{:.3}s", d)).unwrap_or_else(unknown),
+        info.fps.map(|f| format!("{:.3}", f)).unwrap_or_else(unknown),
+        info.video_codec.clone().unwrap_or_else(unknown),
+        info.audio_codec.clone().unwrap_or_else(unknown),
+    ), Some("source_probe"), path.to_str());
+    info
+}
+
+// "30000/1001" -> 29.97
+fn parse_frame_rate(rate: &str) -> Option<f64> {
+    let (num, den) = rate.split_once('/').unwrap_or((rate, "1"));
+    let (num, den): (f64, f64) = (num.trim().parse().ok()?, den.trim().parse().ok()?);
+    (num > 0.0 && den > 0.0).then(|| num / den)
+}
+
+fn probe_metadata(path: &Path) -> Result<ProbeOutput> {
+    let output = Command::new("ffprobe")
+        .arg("-v")
+        .arg("error")
+        .arg("-show_entries")
+        .arg("format=duration:stream=codec_type,codec_name,r_frame_rate")
+        .arg("-of")
+        .arg("json")
+        .arg(path)
+        .output()?;
+
+    Ok(serde_json::from_slice(&output.stdout)?)
+}
+
+impl SourceInfo {
+    // True when the source already has the target aspect ratio (within 1%), so scaling
+    // alone fills the frame and cropping would only lose pixels
+    pub fn matches_aspect(&self, width: u32, height: u32) -> bool {
+        self.dimensions.is_some_and(|(sw, sh)| {
+            let source = sw as f64 / sh as f64;
+            let target = width as f64 / height as f64;
+            ((source - target) / target).abs() < 0.01
+        })
+    }
+}
+
+fn probe_dimensions(path: &Path) -> Result<Option<(u32, u32)>> {
+    let output = Command::new("ffprobe")
+        .arg("-v")
+        .arg("error")
+        .arg("-select_streams")
+        .arg("v:0")
+        .arg("-show_entries")
+        .arg("stream=width,height")
+        .arg("-of")
+        .arg("csv=p=0:s=x")
+        .arg(path)
+        .output()?;
+
+    let text = String::from_utf8_lossy(&output.stdout);
+    let parsed = text.trim().split_once('x')
+        .and_then(|(w, h)| Some((w.trim().parse().ok()?, h.trim().parse().ok()?)))
+        .filter(|&(w, h): &(u32, u32)| w > 0 && h > 0);
+    Ok(parsed)
+}
+
+fn check_audio_stream(path: &Path) -> Result<bool> {
+    let output = Command::new("ffprobe")
+        .arg("-v")
+        .arg("error")
+        .arg("-select_streams")
+        .arg("a")
+        .arg("-show_entries")
+        .arg("stream=codec_type")
+        .arg("-of")
+        .arg("csv=p=0")
+        .arg(path)
+        .output()?;
+    
+    Ok(!output.stdout.is_empty())
+}
+
+// True peak of the rendered audio in dBFS, via ebur128's summary. None when the file has
+// no audio or ffmpeg printed no summary.
+pub(crate) fn measure_true_peak(path: &Path) -> Result<Option<f64>> {
+    let output = run(ffmpeg_command(Vec::new())
+        .arg("-hide_banner")
+        .arg("-nostats")
+        .arg("-i")
+        .arg(path)
+        .arg("-vn")
+        .arg("-af")
+        .arg("ebur128=peak=true")
+        .arg("-f")
+        .arg("null")
+        .arg("-"))?;
+
+    // The summary ends with "True peak:" / "Peak: -1.2 dBFS"; frame lines use "TPK:" instead
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    let peak = stderr.lines()
+        .rev()
+        .find_map(|line| line.trim().strip_prefix("Peak:"))
+        .and_then(|value| value.trim().trim_end_matches("dBFS").trim().parse().ok());
+    Ok(peak)
+}
+
+// Silent stretches of the audio in [start, start + duration) of the file, relative to start,
+// from silencedetect. A silence still running at the end closes at `duration`.
+pub(crate) fn detect_silences(path: &Path, start: f64, duration: f64, noise_db: f64, min_silence: f64) -> Result<Vec<(f64, f64)>> {
+    let output = run(ffmpeg_command(Vec::new())
+        .arg("-hide_banner")
+        .arg("-nostats")
+        .arg("-ss")
+        .arg(format!("{:.3}", start))
+        .arg("-t")
+        .arg(format!("{:.3}", duration))
+        .arg("-i")
+        .arg(path)
+        .arg("-vn")
+        .arg("-af")
+        .arg(format!("silencedetect=noise={}dB:d={}", noise_db, min_silence))
+        .arg("-f")
+        .arg("null")
+        .arg("-"))?;
+
+    // "[silencedetect @ 0x..] silence_start: 1.2" then "... silence_end: 2.9 | silence_duration: 1.7"
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    let value = |line: &str, key: &str| -> Option<f64> {
+        line.split_once(key)?.1.split_whitespace().next()?.parse().ok()
+    };
+    let mut silences = Vec::new();
+    let mut open = None;
+    for line in stderr.lines() {
+        if let Some(at) = value(line, "silence_start:") {
+            open = Some(at);
+        } else if let (Some(from), Some(to)) = (open, value(line, "silence_end:")) {
+            silences.push((from, to));
+            open = None;
+        }
+    }
+    if let Some(from) = open {
+        silences.push((from, duration));
+    }
+    Ok(silences.into_iter().map(|(from, to): (f64, f64)| (from.max(0.0), to.min(duration))).filter(|(from, to)| to > from).collect())
+}
@@ -0,0 +1,1476 @@
+use anyhow::Result;
+use std::collections::HashMap;
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+use crate::analysis::{Analysis, BgmSegment, Cut, Thumbnail, TimeFormat};
+use crate::ass::{fonts_dir, to_ass};
+use crate::command::{
+    build_concat_args, build_preview_args, build_resize_args, build_segment_args, build_stitch_args, build_thumbnail_args,
+    ffmpeg_command, final_mix_filter, stitch_filter, AudioMix, Bgm, BgmSection, FinalMix, JoinedVideo, Pass, SeInput,
+    SegmentCommand, Stitch, Xfade,
+};
+use crate::config::Config;
+use crate::ffmpeg::{kill_running, run, run_with_progress, run_with_retry, EncodeSettings, FfmpegError};
+use crate::filters::{
+    escape_drawtext, escape_expansion, escape_filter_value, get_blur_pad_filter, get_caption_alpha,
+    get_caption_background_filter, get_caption_line_y, get_caption_motion, get_caption_x, get_color_filter,
+    get_cover_filter, get_cover_pan_filter, get_drawtext_config, get_freeze_filters, get_karaoke_filters,
+    get_loudnorm_filter, get_lut_filter, get_pan_filter, get_preview_filter, get_progress_bar_filters, get_shake_filter,
+    get_silence_trim_filters, get_subtitles_filter, get_speed_factor, get_thumbnail_filter, get_transition_filter,
+    get_watermark_filter, get_waveform_filters, has_mixed_emoji, is_font_path, is_no_color_filter, is_valid_color,
+    resolve_color, select_font, wrap_caption,
+};
+use crate::logging::{current_job, job_scope, log_dry_run, log_error, log_json, new_job_id};
+use crate::manifest::{RenderManifest, SeUsage};
+use crate::probe::{detect_silences, measure_true_peak, probe_source, SourceInfo};
+use crate::se::{select_se_file, DEFAULT_SE_VOLUME};
+use crate::subtitles::{to_ffmetadata, to_srt, to_vtt, Cue};
+use crate::temp::{remove_live_temp_files, segment_temp_path, temp_prefix, TempFiles};
+use crate::time::{format_time, parse_time};
+use crate::upload::upload_outputs;
+use crate::validate::is_inside_dir;
+use crate::webhook::{send_webhook, WebhookPayload};
+
+const DEFAULT_TRANSITION_DURATION: f64 = 0.5;
+const DEFAULT_VOICE_BOOST: f64 = 1.3;
+const DEFAULT_SHAKE_AMOUNT: f64 = 0.5;
+const DEFAULT_STILL_FPS: f64 = 30.0;
+const DEFAULT_MAX_CHARS_PER_LINE: usize = 14;
+const CAPTION_FONT_SIZE: u32 = 80;
+const CAPTION_LINE_HEIGHT: u32 = 96;
+const DEFAULT_WATERMARK_OPACITY: f64 = 0.8;
+const DEFAULT_PREVIEW_DURATION: f64 = 2.5;
+const DEFAULT_BGM_SECTION_FADE: f64 = 0.5;
+const DEFAULT_AUDIO_FADE_OUT: f64 = 2.0;
+const DEFAULT_MIN_CUT_DURATION: f64 = 0.3;
+const DEFAULT_SILENCE_THRESHOLD: f64 = -35.0;
+const DEFAULT_SILENCE_MIN_DURATION: f64 = 0.8;
+// Kept on both sides of a trimmed pause so word onsets and tails aren't clipped
+const SILENCE_MARGIN: f64 = 0.15;
+pub(crate) const SHORT_CUT_MODES: [&str; 2] = ["pad", "skip"];
+
+fn resolve_bgm_sections(segments: &[BgmSegment], analysis: &Analysis) -> Vec<BgmSection> {
+    segments.iter().filter_map(|segment| {
+        let path = PathBuf::from(&segment.path);
+        if !path.exists() {
+            log_json("WARN", "BGM section file not found, leaving that section without music", Some("bgm_missing"), path.to_str());
+            return None;
+        }
+        Some(BgmSection {
+            path,
+            start: parse_time(&segment.start, analysis.time_format).ok()?,
+            end: parse_time(&segment.end, analysis.time_format).ok()?,
+            fade: segment.fade.unwrap_or(DEFAULT_BGM_SECTION_FADE),
+        })
+    }).collect()
+}
+
+// Only paths that stay inside assets_dir; render rejects the others up front
+fn watermark_path(analysis: &Analysis, config: &Config) -> Option<PathBuf> {
+    analysis.watermark.as_deref().filter(|watermark| is_inside_dir(watermark)).map(|watermark| config.assets_dir.join(watermark))
+}
+
+// The cut's own LUT, else the Analysis-wide one; like watermark_path, only inside assets_dir
+fn lut_path(cut: &Cut, analysis: &Analysis, config: &Config) -> Option<PathBuf> {
+    cut.lut.as_deref().or(analysis.lut.as_deref()).filter(|lut| is_inside_dir(lut)).map(|lut| config.assets_dir.join(lut))
+}
+
+// resolve_font silently falls back for missing font files; say so once per use
+fn warn_missing_font(font: Option<&str>) {
+    if let Some(path) = font.filter(|f| is_font_path(f) && !Path::new(f).is_file()) {
+        log_json("WARN", "Font file not found, using the default font", Some("font_missing"), Some(path));
+    }
+}
+
+// resolve_color falls back for malformed colors; name the field so the typo can be found
+fn warn_invalid_colors(colors: &[(&str, Option<&String>)]) {
+    for (field, value) in colors {
+        if let Some(value) = value.filter(|v| !is_valid_color(v)) {
+            log_json("WARN", &format!("Invalid {} '{}' (expected a color name, #RRGGBB or 0xRRGGBB), using the default", field, value), Some("color_invalid"), None);
+        }
+    }
+}
+
+fn warn_mixed_emoji(text: &str) {
+    if has_mixed_emoji(text) {
+        log_json("WARN", &format!("Emoji can't be drawn inside regular text and were dropped: {:?}", text), Some("emoji_stripped"), None);
+    }
+}
+
+pub fn generate_thumbnail(
+    video_path: &Path,
+    thumbnail: &Thumbnail,
+    filename: &str,
+    index: Option<usize>,
+    dimensions: (u32, u32),
+    time_format: Option<TimeFormat>,
+    config: &Config,
+) -> Result<Option<PathBuf>> {
+    // timestamp in the Analysis time_format, like every other time
+    // output: output_dir/filename_thumb.jpg, or filename_thumb_{index}.jpg for multiple covers
+    // Returns the written path, or None in dry-run mode or when ffmpeg failed
+    
+    let out_name = match index {
+        Some(i) => format!("{}_thumb_{}.jpg", filename, i),
+        None => format!("{}_thumb.jpg", filename),
+    };
+    let out_path = config.output_dir.join(out_name);
+    warn_mixed_emoji(&thumbnail.text);
+    warn_missing_font(thumbnail.font.as_deref());
+    warn_invalid_colors(&[("thumbnail color", thumbnail.color.as_ref())]);
+    let filter = get_thumbnail_filter(thumbnail, dimensions);
+    let cover = thumbnail.source_image.as_deref().filter(|image| is_inside_dir(image)).map(|image| config.raw_dir.join(image)).filter(|path| {
+        let exists = path.exists();
+        if !exists {
+            log_json("WARN", &format!("Thumbnail source_image {} not found, using the video frame", path.display()), Some("thumbnail_source_missing"), path.to_str());
+        }
+        exists
+    });
+
+    if let Some(cover) = &cover {
+        log_json("INFO", "Generating thumbnail from source_image", Some("thumbnail_gen"), cover.to_str());
+    } else {
+        log_json("INFO", &format!("Generating thumbnail at {}", thumbnail.timestamp), Some("thumbnail_gen"), None);
+    }
+    let at = format!("{:.3}", parse_time(&thumbnail.timestamp, time_format)?);
+    let mut cmd = ffmpeg_command(build_thumbnail_args(video_path, &at, cover.as_deref(), &filter, &out_path));
+
+    if config.dry_run {
+        log_dry_run(&cmd, "Thumbnail (not written)");
+        return Ok(None);
+    }
+
+    let output = run_with_retry("Thumbnail", config.ffmpeg_retries, || run(&mut cmd))?;
+
+    if output.status.success() {
+        log_json("INFO", "Thumbnail generated", Some("thumbnail_success"), Some(out_path.to_str().unwrap_or("")));
+        Ok(Some(out_path))
+    } else {
+        let error = anyhow::Error::new(FfmpegError::from_output("Thumbnail generation failed", &output));
+        log_error("Thumbnail generation failed", Some("thumbnail_failed"), out_path.to_str(), &error);
+        Ok(None)
+    }
+}
+
+// Short looping GIF/WebP around the thumbnail's timestamp, e.g. for platforms that show
+// motion thumbnails. Same naming and return convention as generate_thumbnail.
+pub fn generate_preview(
+    video_path: &Path,
+    thumbnail: &Thumbnail,
+    filename: &str,
+    index: Option<usize>,
+    dimensions: (u32, u32),
+    time_format: Option<TimeFormat>,
+    config: &Config,
+) -> Result<Option<PathBuf>> {
+    let Some(format) = thumbnail.preview.as_deref() else {
+        return Ok(None);
+    };
+    let out_name = match index {
+        Some(i) => format!("{}_preview_{}.{}", filename, i, format),
+        None => format!("{}_preview.{}", filename, format),
+    };
+    let out_path = config.output_dir.join(out_name);
+    let duration = thumbnail.preview_duration.unwrap_or(DEFAULT_PREVIEW_DURATION);
+    let start = (parse_time(&thumbnail.timestamp, time_format)? - duration / 2.0).max(0.0);
+
+    log_json("INFO", &format!("Generating {} preview around {}", format, thumbnail.timestamp), Some("preview_gen"), None);
+
+    let filter = get_preview_filter(format, dimensions);
+    let mut cmd = ffmpeg_command(build_preview_args(video_path, start, duration, format, &filter, &out_path));
+
+    if config.dry_run {
+        log_dry_run(&cmd, "Preview (not written)");
+        return Ok(None);
+    }
+
+    let output = run_with_retry("Preview", config.ffmpeg_retries, || run(&mut cmd))?;
+
+    if output.status.success() {
+        log_json("INFO", "Preview generated", Some("preview_success"), out_path.to_str());
+        Ok(Some(out_path))
+    } else {
+        let error = anyhow::Error::new(FfmpegError::from_output("Preview generation failed", &output));
+        log_error("Preview generation failed", Some("preview_failed"), out_path.to_str(), &error);
+        Ok(None)
+    }
+}
+
+// For a shutdown that can't wait for the render in flight: exiting skips its cleanup, so
+// kill its ffmpeg and remove its temp files first
+pub fn abort_renders() {
+    kill_running();
+    remove_live_temp_files();
+}
+
+// Instructions in a JSON_DIR subfolder render into the same subfolder of OUTPUT_DIR
+fn job_output_dir(analysis: &Analysis, config: &Config) -> PathBuf {
+    analysis.instruction_path.as_ref()
+        .and_then(|path| path.parent()?.strip_prefix(&config.json_dir).ok())
+        .map(|relative| config.output_dir.join(relative))
+        .unwrap_or_else(|| config.output_dir.clone())
+}
+
+// Render an Analysis end to end. Returns the output path, or the ffmpeg error if the final mix failed.
+// A {filename}.result.json manifest is written to the output dir either way.
+// Entries logged while rendering carry a job ID: the caller's job_scope if it set one,
+// otherwise one derived from the instruction file
+pub fn process_instruction(analysis: &Analysis, config: &Config) -> Result<PathBuf> {
+    let _job = current_job().is_none().then(|| job_scope(&new_job_id(analysis.instruction_path.as_deref())));
+    let job_config;
+    let output_dir = job_output_dir(analysis, config);
+    let config = if output_dir != config.output_dir {
+        if !config.dry_run {
+            fs::create_dir_all(&output_dir)?;
+        }
+        job_config = Config { output_dir, ..config.clone() };
+        &job_config
+    } else {
+        config
+    };
+
+    // A skipped job still gets its manifest and webhook, so callers hear about it either way
+    let mut manifest = RenderManifest::default();
+    let output_path = config.output_dir.join(analysis.output_filename());
+    let result = if !analysis.force.unwrap_or(false) && is_up_to_date(&output_path, analysis, config) {
+        log_json("INFO", "Output is newer than its inputs, skipping (set \"force\": true to re-render)", Some("render_skipped"), output_path.to_str());
+        manifest.skipped = true;
+        Ok(output_path)
+    } else {
+        render(analysis, config, &mut manifest)
+    };
+
+    match &result {
+        Ok(path) => {
+            manifest.success = true;
+            manifest.output = Some(path.clone());
+        }
+        Err(e) => manifest.error = Some(e.to_string()),
+    }
+
+    // Upload only what was actually produced; local copies stay in place either way
+    if let (Some(s3), Ok(path), false) = (&config.s3, &result, config.dry_run || manifest.skipped) {
+        let mut files = vec![path.clone()];
+        files.extend(manifest.thumbnails.iter().cloned());
+        files.extend(manifest.previews.iter().cloned());
+        files.extend(manifest.subtitles.iter().cloned());
+        manifest.uploads = upload_outputs(s3, &files);
+    }
+
+    let manifest_path = RenderManifest::path_for(&config.output_dir, &analysis.output_filename());
+    if config.dry_run {
+        log_json("INFO", "[dry-run] Result manifest (not written)", Some("dry_run"), manifest_path.to_str());
+    } else if let Err(e) = manifest.write(&manifest_path) {
+        log_json("ERROR", &format!("Failed to write result manifest: {}", e), Some("manifest_error"), manifest_path.to_str());
+    } else {
+        log_json("INFO", "Result manifest written", Some("manifest_written"), manifest_path.to_str());
+    }
+
+    if let Some(url) = analysis.webhook_url.as_ref().or(config.webhook_url.as_ref()) {
+        let payload = WebhookPayload {
+            success: manifest.success,
+            skipped: manifest.skipped,
+            original_filename: &analysis.original_filename,
+            output: manifest.output.as_deref(),
+            duration: manifest.total_duration,
+            error: manifest.error.as_deref(),
+        };
+        if config.dry_run {
+            log_json("INFO", "[dry-run] Webhook (not sent)", Some("dry_run"), Some(url));
+        } else {
+            send_webhook(url, &payload);
+        }
+    }
+
+    result
+}
+
+// True when the output exists and is newer than the raw input(s) and the instruction file,
+// and than any BGM, LUT, watermark or (for instructions with SE events) SE directory file
+// that is present. A missing asset doesn't force a re-render by itself.
+fn is_up_to_date(output_path: &Path, analysis: &Analysis, config: &Config) -> bool {
+    let modified = |path: &Path| fs::metadata(path).and_then(|m| m.modified()).ok();
+    let Some(output_time) = modified(output_path) else {
+        return false;
+    };
+    let mut inputs = vec![config.raw_dir.join(&analysis.original_filename)];
+    inputs.extend(analysis.cuts.iter().filter_map(|cut| cut_source_path(cut, config)));
+    inputs.extend(analysis.instruction_path.clone());
+
+    let mut assets = match &analysis.bgm_segments {
+        Some(segments) => segments.iter().map(|segment| PathBuf::from(&segment.path)).collect(),
+        None => vec![analysis.bgm_path.as_ref().map(PathBuf::from).unwrap_or_else(|| config.bgm_path.clone())],
+    };
+    assets.extend(watermark_path(analysis, config));
+    assets.extend(analysis.cuts.iter().filter_map(|cut| lut_path(cut, analysis, config)));
+    // SE files are picked per event by tag and type, so any change in the library counts
+    if analysis.se_events.as_ref().is_some_and(|events| !events.is_empty()) {
+        if let Ok(entries) = fs::read_dir(&config.se_dir) {
+            assets.extend(entries.flatten().map(|entry| entry.path()));
+        }
+    }
+
+    inputs.iter().all(|input| modified(input).is_some_and(|t| t < output_time))
+        && !assets.iter().any(|asset| modified(asset).is_some_and(|t| t >= output_time))
+}
+
+fn render(analysis: &Analysis, config: &Config, manifest: &mut RenderManifest) -> Result<PathBuf> {
+    let video_path = config.raw_dir.join(&analysis.original_filename);
+    let output_path = config.output_dir.join(analysis.output_filename());
+    let prefix = temp_prefix(analysis);
+
+    // NUE_KEEP_TEMP: this job's temp files go in their own folder and stay there for inspection
+    let job_config;
+    let config = if config.keep_temp {
+        job_config = Config { temp_dir: config.temp_dir.join(&prefix), ..config.clone() };
+        &job_config
+    } else {
+        config
+    };
+    let temp_dir = config.temp_dir.clone();
+    
+    // Create temp directory
+    fs::create_dir_all(&temp_dir)?;
+    if config.keep_temp {
+        log_json("INFO", &format!("Keeping temp files in {:?}", temp_dir), Some("keep_temp"), None);
+    }
+    
+    // BGM path with fallback to default
+    let mut bgm_path_buf = analysis.bgm_path.as_ref()
+        .map(PathBuf::from)
+        .unwrap_or_else(|| config.bgm_path.clone());
+    
+    // If BGM file doesn't exist, try default_bgm.mp3
+    if !bgm_path_buf.exists() {
+        if let Some(bgm_dir) = config.bgm_path.parent() {
+            let default_bgm = bgm_dir.join("default_bgm.mp3");
+            if default_bgm.exists() {
+                bgm_path_buf = default_bgm;
+            }
+        }
+    }
+    
+    // Timed sections replace the single bed; missing files are dropped from the mix
+    let bgm_sections = analysis.bgm_segments.as_ref().map(|segments| resolve_bgm_sections(segments, analysis));
+    let has_bgm = match &bgm_sections {
+        _ if analysis.is_preview() => false,
+        Some(sections) => !sections.is_empty(),
+        None => bgm_path_buf.exists(),
+    };
+    if analysis.is_preview() {
+        log_json("INFO", "Preview render: reduced resolution, no BGM/SE, no thumbnails", Some("preview_mode"), None);
+    }
+    let se_events = analysis.se_events.as_ref();
+    let has_se = !analysis.is_preview() && se_events.map(|se| !se.is_empty()).unwrap_or(false);
+    // The voice boost only lifts the voice above BGM/SE; with nothing to mix it sits at unity
+    let voice_boost = if has_bgm || has_se { analysis.audio_boost.unwrap_or(DEFAULT_VOICE_BOOST) } else { 1.0 };
+
+    // Every segment overlays the watermark, so a bad path fails before any encoding
+    if let Some(watermark) = analysis.watermark.as_deref().filter(|watermark| !is_inside_dir(watermark)) {
+        return Err(anyhow::anyhow!("Watermark '{}' must be a relative path inside the assets dir", watermark));
+    }
+    if let Some(watermark) = watermark_path(analysis, config) {
+        if !watermark.exists() {
+            return Err(anyhow::anyhow!("Watermark asset not found: {:?}", watermark));
+        }
+    }
+
+    // Same for LUTs: a missing grade would otherwise surface one segment at a time
+    for cut in &analysis.cuts {
+        if let Some(lut) = cut.lut.as_deref().or(analysis.lut.as_deref()).filter(|lut| !is_inside_dir(lut)) {
+            return Err(anyhow::anyhow!("LUT '{}' must be a relative path inside the assets dir", lut));
+        }
+        if let Some(lut) = lut_path(cut, analysis, config).filter(|lut| !lut.exists()) {
+            return Err(anyhow::anyhow!("LUT not found: {:?}", lut));
+        }
+    }
+
+    log_json("INFO", &format!("Processing video: {:?}", video_path), Some("process_start"), None);
+    log_json("INFO", &format!("BGM: {:?}, exists: {}", bgm_path_buf, has_bgm), Some("bgm_check"), None);
+    
+    // Everything tracked here is removed when the render returns, including on early errors
+    let mut temp_files = TempFiles::new(config.keep_temp);
+    
+    // Silent sources get a generated track per segment so every later step can assume audio
+    let source = probe_source(&video_path);
+    if !source.has_audio {
+        log_json("WARN", "Source has no audio stream, substituting silence", Some("source_silent"), video_path.to_str());
+    }
+    
+    // Step 1: Process each cut as individual segment, in render order. Everything after this
+    // works on the cuts that actually rendered, which is all of them unless continue_on_error
+    // skipped some. Segments keep their array index in names and logs.
+    let mut segment_paths = Vec::new();
+    let mut cuts = Vec::new();
+    let mut probed = HashMap::new();
+    let continue_on_error = analysis.continue_on_error.unwrap_or(false);
+    
+    for (i, cut) in analysis.ordered_cuts() {
+        temp_files.track(segment_temp_path(&temp_dir, &prefix, i));
+        let prepared = prepare_cut(i, cut, &video_path, &source, &mut probed, analysis, config).and_then(|(cut, input, input_info)| {
+            // Muted cuts render like a silent source: generated silence keeps the joined audio
+            // stream continuous
+            let input_info = if cut.mute.unwrap_or(false) {
+                log_json("INFO", &format!("Segment {}: muted", i), Some("cut_muted"), None);
+                SourceInfo { has_audio: false, ..input_info }
+            } else {
+                input_info
+            };
+            let Some(cut) = lengthen_short_cut(i, &cut, &input_info, analysis)? else {
+                return Ok(None);
+            };
+            let cut = find_silences(i, cut, &input, &input_info, analysis);
+            // Once any cut sets a volume the global boost is off, so the rest get it here
+            let cut = match cut.volume {
+                None if analysis.has_cut_volumes() => {
+                    let reason = if has_bgm || has_se { "audio_boost" } else { "no BGM/SE, so no boost" };
+                    log_json("INFO", &format!("Segment {}: no volume set, using {} ({})", i, voice_boost, reason), Some("cut_volume"), None);
+                    Cut { volume: Some(voice_boost), ..cut }
+                }
+                _ => cut,
+            };
+            Ok(Some((cut, input, input_info)))
+        });
+        let rendered = match prepared {
+            Ok(Some((cut, input, input_info))) => process_single_segment(i, &cut, &input, &input_info, &prefix, analysis, config).map(|path| (path, cut)),
+            Ok(None) => {
+                manifest.skipped_segments.push(i);
+                continue;
+            }
+            Err(e) => Err(e),
+        };
+        match rendered {
+            Ok((segment_path, cut)) => {
+                segment_paths.push(segment_path);
+                cuts.push(cut);
+            }
+            Err(e) if continue_on_error => {
+                log_error(&format!("Skipping segment {}: {}", i, e), Some("segment_skipped"), None, &e);
+                manifest.skipped_segments.push(i);
+            }
+            Err(e) => return Err(e),
+        }
+    }
+    if segment_paths.is_empty() {
+        return Err(anyhow::anyhow!("No segment rendered (all failed or were skipped)"));
+    }
+    
+    log_json("INFO", &format!("Processed {} segments", segment_paths.len()), Some("segments_complete"), None);
+    manifest.segments = segment_paths.len();
+    
+    // Step 2: Join segments. Cuts with a transition_type need xfade (re-encode), as do
+    // segments whose streams may not line up for copying (concat_reencode overrides the
+    // guess); otherwise the concat demuxer can stitch them without touching the streams.
+    let concat_file = temp_files.track(temp_dir.join(format!("{}_concat_list.txt", prefix)));
+    let stitched_path = temp_files.track(temp_dir.join(format!("{}_stitched.mp4", prefix)));
+    let has_transitions = cuts.iter().skip(1).any(|cut| cut.transition_type.is_some());
+    let reencode = segment_paths.len() > 1
+        && analysis.concat_reencode.unwrap_or_else(|| needs_concat_reencode(&cuts, analysis));
+    if reencode && !has_transitions {
+        log_json("INFO", "Joining segments with a re-encode instead of stream copy", Some("concat_reencode"), None);
+    }
+    let mut transition_overlap = 0.0;
+    
+    let overlaps = transition_overlaps(&cuts, analysis);
+    let cues = caption_cues(&cuts, analysis, &overlaps);
+    
+    // libass captions for the whole video, burned in below
+    let ass_path = temp_files.track(temp_dir.join(format!("{}_captions.ass", prefix)));
+    let ass = analysis.uses_libass().then(|| to_ass(&cues, &cuts, analysis.output_dimensions(), CAPTION_FONT_SIZE, DEFAULT_MAX_CHARS_PER_LINE));
+    let burn_captions = ass.is_some() && !cues.is_empty();
+    if let Some(ass) = ass.as_ref().filter(|_| burn_captions && !config.dry_run) {
+        fs::write(&ass_path, ass)?;
+    }
+    
+    // A waveform overlay, progress bar or burned-in captions draw on the joined video, so the
+    // final mix re-encodes it
+    let waveform = analysis.waveform.as_deref();
+    let progress_bar = analysis.progress_bar.unwrap_or(false);
+    let draws_video = waveform.is_some() || burn_captions || progress_bar;
+    // The mixed audio feeds the waveform too, so it's split off from an intermediate label
+    let mix_label = if waveform.is_some() { "[mixed]" } else { "[aout]" };
+    let joined = if has_transitions || reencode {
+        transition_overlap = stitch_with_transitions(&segment_paths, &cuts, analysis, &overlaps, &stitched_path, config)?;
+        JoinedVideo::Stitched(&stitched_path)
+    } else {
+        let mut file = fs::File::create(&concat_file)?;
+        for seg in &segment_paths {
+            writeln!(file, "file '{}'", seg.display())?;
+        }
+        drop(file);
+        JoinedVideo::ConcatList(&concat_file)
+    };
+    
+    
+    // Step 3: Add BGM/SE on top of the joined video
+    
+    // Final length, used for the BGM fade-out and to strictly limit the output. Transitions
+    // overlap neighbouring segments, shortening the final timeline.
+    let total_duration = total_duration(&cuts, analysis) - transition_overlap;
+    let video_graph = draws_video.then(|| {
+        final_video_graph(waveform, progress_bar, burn_captions.then_some(ass_path.as_path()), &cuts, total_duration, analysis, config)
+    });
+
+    // Chapters ride along as an extra input ahead of the audio inputs, and are mapped from it
+    let mut chapters_path = None;
+    if analysis.emit_chapters.unwrap_or(false) {
+        let path = temp_files.track(temp_dir.join(format!("{}_chapters.txt", prefix)));
+        let chapters = chapter_marks(&cuts, analysis, &overlaps, total_duration);
+        if !config.dry_run {
+            fs::write(&path, to_ffmetadata(&chapters))?;
+        }
+        log_json("INFO", &format!("{} chapters", chapters.len()), Some("chapters"), path.to_str());
+        chapters_path = Some(path);
+    }
+    
+    // Build audio filter for BGM and sound effects
+    let loudnorm = analysis.target_lufs.map(get_loudnorm_filter);
+    
+    // Resolve SE files up front: the BGM dips below only follow SEs that will play
+    let se_files: Vec<_> = se_events.filter(|_| has_se).into_iter().flatten().filter_map(|se| {
+        let file = select_se_file(se, &config.se_dir, &config.se_map);
+        if file.is_none() {
+            log_json("WARN", &format!("Skipping SE: type='{}' at {}", se.event_type, se.timestamp), Some("se_skipped"), None);
+        }
+        Some((se, file?))
+    }).collect();
+    
+    let audio = (has_bgm || has_se).then(|| {
+        let ducking = has_bgm && analysis.bgm_ducking.unwrap_or(false);
+        let se_ducking = has_bgm && !se_files.is_empty() && analysis.se_ducking.unwrap_or(false);
+        
+        // Boost the video's own audio (audio_boost, default 1.3) so it sits above BGM/SE.
+        // Per-cut volumes already carry the boost from the segment step, so don't apply it twice.
+        let voice_volume = if analysis.has_cut_volumes() { 1.0 } else { voice_boost };
+        log_json("INFO", &format!("Voice audio boost {}", voice_volume), Some("audio_boost"), None);
+
+        let bgm = match &bgm_sections {
+            _ if !has_bgm => None,
+            Some(sections) => {
+                manifest.bgm_segments.extend(sections.iter().map(|section| section.path.clone()));
+                log_json("INFO", &format!("BGM: {} timed sections", sections.len()), Some("bgm_sections"), None);
+                Some(Bgm::Sections(sections))
+            }
+            None => {
+                manifest.bgm = Some(bgm_path_buf.clone());
+                Some(Bgm::Track { path: &bgm_path_buf, fade_in: analysis.bgm_fade_in })
+            }
+        };
+        if ducking {
+            log_json("INFO", "BGM ducking enabled (sidechain keyed on video audio)", Some("bgm_ducking"), None);
+        }
+        if se_ducking {
+            log_json("INFO", &format!("BGM dips under {} SEs", se_files.len()), Some("se_ducking"), None);
+        }
+        
+        let se = se_files.iter().map(|(se, se_file)| {
+            // V14 DEBUG: Log every SE attempt (NUE_LOG_LEVEL=debug)
+            log_json("DEBUG", &format!("Processing SE: type='{}', path='{:?}'", se.event_type, se_file), Some("se_debug"), None);
+            manifest.se.push(SeUsage { timestamp: se.timestamp.clone(), file: se_file.clone() });
+            let pan = se.pan.as_deref().and_then(|pan| {
+                let filter = get_pan_filter(pan);
+                if filter.is_none() {
+                    log_json("WARN", &format!("Unknown SE pan '{}', leaving unpanned", pan), Some("se_pan_unknown"), None);
+                }
+                filter
+            });
+            SeInput {
+                path: se_file,
+                at: parse_time(&se.timestamp, analysis.time_format).unwrap_or(0.0),
+                // V14 ADJUSTMENT: Boost synth SE volume to 0.8 (was 0.2)
+                // Synthetic assets are quieter/unmastered, so they need more gain.
+                volume: se.volume.unwrap_or(DEFAULT_SE_VOLUME),
+                pan,
+            }
+        }).collect();
+        
+        AudioMix {
+            voice_volume,
+            bgm,
+            bgm_loop: analysis.bgm_loop.unwrap_or(false),
+            ducking,
+            se_ducking,
+            se,
+            fade_out: analysis.audio_fade_out.unwrap_or(DEFAULT_AUDIO_FADE_OUT).min(total_duration),
+            limiter: analysis.limiter.unwrap_or(true),
+        }
+    });
+    
+    manifest.total_duration = total_duration;
+    
+    let settings = EncodeSettings::from_analysis(analysis);
+    let mix = FinalMix {
+        joined,
+        chapters: chapters_path.as_deref(),
+        audio,
+        loudnorm: loudnorm.as_deref(),
+        video_graph: video_graph.as_deref(),
+        mix_label,
+        encoder: config.video_encoder,
+        settings: &settings,
+        duration: total_duration,
+        output: &output_path,
+    };
+    if let Some(filter) = final_mix_filter(&mix) {
+        log_json("INFO", &format!("Audio filter: {}", filter), Some("filter_debug"), None);
+    }
+    let mut concat_cmd = ffmpeg_command(build_concat_args(&mix));
+    
+    let mut concat_error = None;
+    if config.dry_run {
+        log_dry_run(&concat_cmd, "Final mix (not written)");
+    } else {
+        let output = run_with_retry("Final mix", config.ffmpeg_retries, || {
+            run_with_progress(&mut concat_cmd, total_duration, "Final mix")
+        })?;
+        if output.status.success() {
+            log_json("INFO", "Video processing complete", Some("transcode_complete"), Some(output_path.to_str().unwrap_or("")));
+            if let Some(cap) = analysis.max_output_bytes {
+                let temp_path = temp_files.track(temp_dir.join(format!("{}_resized.{}", prefix, analysis.encode_format().extension())));
+                if let Err(e) = fit_output_size(&output_path, &temp_path, cap, total_duration, analysis, config, manifest) {
+                    log_error(&e.to_string(), Some("output_too_large"), output_path.to_str(), &e);
+                    concat_error = Some(e);
+                }
+            }
+            if analysis.check_clipping.unwrap_or(false) {
+                check_clipping(&output_path, manifest);
+            }
+        } else {
+            let error = anyhow::Error::new(FfmpegError::from_output("Concatenation failed", &output));
+            log_error(&error.to_string(), Some("transcode_failed"), output_path.to_str(), &error);
+            concat_error = Some(error);
+        }
+    }
+    
+    // Step 3b: Sidecar subtitles on the same timeline as the final mix
+    if analysis.emit_subtitles.unwrap_or(false) {
+        let ass = ass.unwrap_or_else(|| to_ass(&cues, &cuts, analysis.output_dimensions(), CAPTION_FONT_SIZE, DEFAULT_MAX_CHARS_PER_LINE));
+        for (extension, contents) in [("srt", to_srt(&cues)), ("vtt", to_vtt(&cues)), ("ass", ass)] {
+            let path = output_path.with_extension(extension);
+            if config.dry_run {
+                log_json("INFO", &format!("[dry-run] Subtitles (not written): {} cues", cues.len()), Some("dry_run"), path.to_str());
+                continue;
+            }
+            match fs::write(&path, contents) {
+                Ok(()) => {
+                    log_json("INFO", &format!("Wrote {} subtitle cues", cues.len()), Some("subtitles_written"), path.to_str());
+                    manifest.subtitles.push(path);
+                }
+                Err(e) => log_json("ERROR", &format!("Failed to write subtitles: {}", e), Some("subtitles_error"), path.to_str()),
+            }
+        }
+    }
+    
+    // Step 4: Generate thumbnails. A single legacy `thumbnail` keeps the unindexed _thumb.jpg name.
+    // Previews skip them so a low-res pass never overwrites the real covers.
+    let thumbnails = if analysis.is_preview() { Vec::new() } else { analysis.thumbnail_list() };
+    let indexed = analysis.thumbnails.is_some();
+    for (i, thumb) in thumbnails.into_iter().enumerate() {
+        let index = if indexed { Some(i) } else { None };
+        if !(thumb.preview.is_some() && thumb.preview_only.unwrap_or(false)) {
+            match generate_thumbnail(&video_path, thumb, &analysis.original_filename, index, analysis.output_dimensions(), analysis.time_format, config) {
+                Ok(Some(path)) => manifest.thumbnails.push(path),
+                Ok(None) => {}
+                Err(e) => log_error(&format!("Thumbnail generation failed: {}", e), Some("thumbnail_error"), None, &e),
+            }
+        }
+        match generate_preview(&video_path, thumb, &analysis.original_filename, index, analysis.output_dimensions(), analysis.time_format, config) {
+            Ok(Some(path)) => manifest.previews.push(path),
+            Ok(None) => {}
+            Err(e) => log_error(&format!("Preview generation failed: {}", e), Some("preview_error"), None, &e),
+        }
+    }
+    
+    if let Some(error) = concat_error {
+        return Err(error);
+    }
+    
+    Ok(output_path)
+}
+
+// Graph tail that draws on the joined [0:v] and ends in [vout]. A waveform splits the
+// finished [mixed] audio into [aout] and a visualizer, keyed off the actual mix so it moves
+// with what is heard; the progress bar and burned-in captions go on top of it.
+fn final_video_graph(
+    waveform: Option<&str>,
+    progress_bar: bool,
+    captions: Option<&Path>,
+    cuts: &[Cut],
+    duration: f64,
+    analysis: &Analysis,
+    config: &Config,
+) -> String {
+    let mut graph = String::new();
+    let mut video = "[0:v]";
+    let (width, height) = analysis.output_dimensions();
+    if let Some(style) = waveform {
+        let band = match analysis.waveform_height {
+            Some(band) if analysis.is_preview() => (band / 2).max(1),
+            Some(band) => band,
+            None => height / 6,
+        };
+        let position = analysis.waveform_position.as_deref().unwrap_or("bottom");
+        let (visualizer, overlay) = get_waveform_filters(style, position, width, band);
+        log_json("INFO", &format!("Waveform overlay '{}' at {} ({}px)", style, position, band), Some("waveform"), None);
+        graph.push_str(&format!(";[mixed]asplit=2[aout][wave_a];[wave_a]{}[waves];{}[waves]{}[vwave]", visualizer, video, overlay));
+        video = "[vwave]";
+    }
+    if progress_bar {
+        let position = analysis.progress_bar_position.as_deref().unwrap_or("bottom");
+        warn_invalid_colors(&[("progress_bar_color", analysis.progress_bar_color.as_ref())]);
+        let color = resolve_color(analysis.progress_bar_color.as_deref(), "white");
+        let (source, overlay) = get_progress_bar_filters(position, &color, width, height, duration);
+        log_json("INFO", &format!("Progress bar at {} in {}", position, color), Some("progress_bar"), None);
+        graph.push_str(&format!(";{}[bar];{}[bar]{}[vbar]", source, video, overlay));
+        video = "[vbar]";
+    }
+
+    let mut chain = Vec::new();
+    if let Some(path) = captions {
+        log_json("INFO", "Burning in captions with libass", Some("captions_libass"), path.to_str());
+        chain.push(get_subtitles_filter(path, &fonts_dir(cuts)));
+    }
+    chain.extend(config.video_encoder.upload_filter().map(String::from));
+    if chain.is_empty() {
+        chain.push("null".to_string());
+    }
+    graph.push_str(&format!(";{}{}[vout]", video, chain.join(",")));
+    graph
+}
+
+// Leave room for container overhead and encoder overshoot when picking a bitrate
+const SIZE_HEADROOM: f64 = 0.95;
+const MAX_SIZE_ATTEMPTS: u32 = 3;
+const MIN_VIDEO_KBPS: f64 = 100.0;
+
+// Enforce max_output_bytes: while the file is over the cap, re-encode the original render
+// (video only, audio copied) at a bitrate estimated from the cap and the duration, shrinking
+// the estimate by how far each attempt overshot. The first fitting attempt replaces the output.
+fn fit_output_size(
+    output_path: &Path,
+    temp_path: &Path,
+    cap: u64,
+    duration: f64,
+    analysis: &Analysis,
+    config: &Config,
+    manifest: &mut RenderManifest,
+) -> Result<()> {
+    let size = fs::metadata(output_path)?.len();
+    manifest.output_bytes = Some(size);
+    if size <= cap {
+        return Ok(());
+    }
+
+    let mut settings = EncodeSettings::from_analysis(analysis);
+    let audio_kbps: f64 = settings.audio_bitrate.trim_end_matches('k').parse().unwrap_or(128.0);
+    let mut video_kbps = cap as f64 * 8.0 * SIZE_HEADROOM / duration.max(0.001) / 1000.0 - audio_kbps;
+    log_json("WARN", &format!("Output is {} bytes, over max_output_bytes {}; re-encoding", size, cap), Some("output_too_large"), output_path.to_str());
+
+    for attempt in 1..=MAX_SIZE_ATTEMPTS {
+        if video_kbps < MIN_VIDEO_KBPS {
+            anyhow::bail!("max_output_bytes {} needs a video bitrate below {}k for {:.1}s", cap, MIN_VIDEO_KBPS, duration);
+        }
+        settings.target_bitrate = Some(format!("{}k", video_kbps.round()));
+        let mut cmd = ffmpeg_command(build_resize_args(output_path, config.video_encoder, &settings, temp_path));
+
+        let label = format!("Size re-encode {}", attempt);
+        let output = run_with_retry(&label, config.ffmpeg_retries, || run_with_progress(&mut cmd, duration, &label))?;
+        if !output.status.success() {
+            return Err(FfmpegError::from_output(format!("{} failed", label), &output).into());
+        }
+        let size = fs::metadata(temp_path)?.len();
+        log_json("INFO", &format!("{} at {:.0}k: {} bytes (cap {})", label, video_kbps, size, cap), Some("output_resize"), output_path.to_str());
+        if size <= cap {
+            fs::rename(temp_path, output_path)?;
+            manifest.output_bytes = Some(size);
+            return Ok(());
+        }
+        video_kbps *= cap as f64 / size as f64 * SIZE_HEADROOM;
+    }
+    Err(anyhow::anyhow!("Could not fit the output under max_output_bytes {} in {} attempts", cap, MAX_SIZE_ATTEMPTS))
+}
+
+// Post-render quality gate: measure the final mix's true peak, record it in the manifest
+// and WARN when it goes over full scale. Measurement failures only log.
+fn check_clipping(output_path: &Path, manifest: &mut RenderManifest) {
+    match measure_true_peak(output_path) {
+        Ok(Some(peak)) => {
+            manifest.peak_dbfs = Some(peak);
+            manifest.clipped = Some(peak > 0.0);
+            if peak > 0.0 {
+                log_json("WARN", &format!("Final mix clips: true peak {:.1} dBFS", peak), Some("audio_clipping"), output_path.to_str());
+            } else {
+                log_json("INFO", &format!("Final mix true peak {:.1} dBFS", peak), Some("audio_peak"), output_path.to_str());
+            }
+        }
+        Ok(None) => log_json("WARN", "Could not read the final mix's true peak", Some("audio_peak"), output_path.to_str()),
+        Err(e) => log_json("WARN", &format!("Peak measurement failed: {}", e), Some("audio_peak"), output_path.to_str()),
+    }
+}
+
+// Whether copying segments through the concat demuxer is unsafe. Retimed segments
+// (slowmo/speedup) come out of setpts with irregular timestamps that a stream copy carries
+// into the joined file as stutters or dropped frames. Stills and other source files have
+// their own frame rate and codec parameters, and a muted cut's generated silence may not
+// match the source's sample rate or layout.
+fn needs_concat_reencode(cuts: &[Cut], analysis: &Analysis) -> bool {
+    cuts.iter().any(|cut| {
+        if cut.source.is_some() || cut.mute.unwrap_or(false) {
+            return true;
+        }
+        let start = parse_time(&cut.start_time, analysis.time_format);
+        let end = parse_time(&cut.end_time, analysis.time_format);
+        matches!((start, end), (Ok(start), Ok(end)) if speed_window(cut, start, end, analysis).is_some())
+    })
+}
+
+// Join segments with xfade/acrossfade at boundaries whose incoming cut has a transition_type.
+// Boundaries without one are joined with a hard concat inside the same graph.
+// Returns the total overlap in seconds consumed by the transitions.
+fn stitch_with_transitions(
+    segment_paths: &[PathBuf],
+    cuts: &[Cut],
+    analysis: &Analysis,
+    overlaps: &[f64],
+    output_path: &Path,
+    config: &Config,
+) -> Result<f64> {
+    let settings = EncodeSettings::from_analysis(analysis);
+    let mut joins = Vec::with_capacity(cuts.len().saturating_sub(1));
+    let mut timeline = rendered_duration(&cuts[0], analysis)?;
+    let mut total_overlap = 0.0;
+
+    for (i, cut) in cuts.iter().enumerate().skip(1) {
+        let duration = rendered_duration(cut, analysis)?;
+        match cut.transition_type.as_deref() {
+            Some(name) => {
+                let overlap = overlaps[i];
+                let transition = get_transition_filter(name, cut.transition_direction.as_deref());
+                let offset = (timeline - overlap).max(0.0);
+                log_json("INFO", &format!("Transition '{}' (requested '{}') between segments {} and {} at {:.3}s",
+                    transition, name, i - 1, i, offset), Some("transition_applied"), None);
+                joins.push(Some(Xfade { transition, duration: overlap, offset }));
+                timeline += duration - overlap;
+                total_overlap += overlap;
+            }
+            None => {
+                log_json("INFO", &format!("Hard cut between segments {} and {}", i - 1, i), Some("transition_applied"), None);
+                joins.push(None);
+                timeline += duration;
+            }
+        }
+    }
+
+    let stitch = Stitch {
+        segments: segment_paths,
+        joins: &joins,
+        encoder: config.video_encoder,
+        settings: &settings,
+        output: output_path,
+    };
+    log_json("INFO", &format!("Transition filter: {}", stitch_filter(&stitch)), Some("filter_debug"), None);
+    let mut cmd = ffmpeg_command(build_stitch_args(&stitch));
+
+    if config.dry_run {
+        log_dry_run(&cmd, "Transition stitch (not written)");
+        return Ok(total_overlap);
+    }
+    
+    let output = run_with_retry("Transition stitch", config.ffmpeg_retries, || {
+        run_with_progress(&mut cmd, timeline, "Transition stitch")
+    })?;
+    
+    if !output.status.success() {
+        return Err(FfmpegError::from_output("Transition stitching failed", &output).into());
+    }
+    
+    Ok(total_overlap)
+}
+
+// Process a single segment with filters and effects
+pub fn process_single_segment(
+    index: usize,
+    cut: &Cut,
+    video_path: &Path,
+    source: &SourceInfo,
+    temp_prefix: &str,
+    analysis: &Analysis,
+    config: &Config,
+) -> Result<PathBuf> {
+    let time_format = analysis.time_format;
+    let (start_seconds, end_seconds) = cut_times(index, cut, analysis)?;
+    let duration = end_seconds - start_seconds;
+    
+    if duration <= 0.0 {
+        return Err(anyhow::anyhow!(
+            "Cut {}: end_time '{}' is not after start_time '{}'", index, cut.end_time, cut.start_time
+        ));
+    }
+    
+    let segment_path = segment_temp_path(&config.temp_dir, temp_prefix, index);
+    
+    // Build video filter chain
+    let mut filters = Vec::new();
+    
+    // 0. Trimmed pauses go first, so everything below runs on the tightened clock
+    let trim = (!cut.silences.is_empty()).then(|| get_silence_trim_filters(&cut.silences));
+    filters.extend(trim.as_ref().map(|(video, _)| video.clone()));
+    let duration = duration - cut.silence_removed();
+    
+    // 1. Scale to cover the output frame, then crop horizontally around the focus point
+    //    (or pan between two focus points over the cut). Sources already at the target
+    //    aspect are only scaled; fit_mode "blur_pad" letterboxes over a blurred copy.
+    let (width, height) = analysis.output_dimensions();
+    if source.matches_aspect(width, height) {
+        log_json("INFO", &format!("Segment {}: source already {}:{} aspect, scaling without crop", index, width, height), Some("crop_skipped"), None);
+        filters.push(format!("scale={}:{},setsar=1", width, height));
+    } else if analysis.fit_mode.as_deref() == Some("blur_pad") {
+        filters.push(get_blur_pad_filter(width, height));
+    } else if let Some([from, to]) = cut.focus_path {
+        filters.push(get_cover_pan_filter(width, height, from, to, duration));
+    } else {
+        let focus = cut.focus_point.unwrap_or(0.5);
+        filters.push(get_cover_filter(width, height, focus));
+    }
+    
+    // 2. Apply color filter ("none"/empty is a no-op), then the LUT grade if any
+    if let Some(color_filter) = get_color_filter(&cut.filter) {
+        filters.push(color_filter.to_string());
+    } else if !is_no_color_filter(&cut.filter) {
+        log_json("WARN", &format!("Unknown color filter '{}' on segment {}, ignoring", cut.filter, index), Some("filter_unknown"), None);
+    }
+    if let Some(lut) = lut_path(cut, analysis, config) {
+        filters.push(get_lut_filter(&lut));
+    }
+    
+    // 3. Visual effects (zoom, shake), timed on original_filename so not for cuts with their
+    //    own source. Speed effects are applied separately below.
+    if let Some(effects) = analysis.visual_effects.as_ref().filter(|_| cut.source.is_none()) {
+        for effect in effects {
+            if get_speed_factor(&effect.effect_type, None).is_some() {
+                continue;
+            }
+            if let Ok(effect_start) = parse_time(&effect.start, time_format) {
+                if effect_start >= start_seconds && effect_start < end_seconds {
+                    match effect.effect_type.as_str() {
+                        "zoom_in" => filters.push(format!("crop=iw/1.25:ih/1.25:(iw-out_w)/2:(ih-out_h)/2,scale={}:{}", width, height)),
+                        "zoom_out" => filters.push(format!("crop=iw/1.1:ih/1.1:(iw-out_w)/2:(ih-out_h)/2,scale={}:{}", width, height)),
+                        "shake" => {
+                            let effect_end = parse_time(&effect.end, time_format).unwrap_or(end_seconds).min(end_seconds);
+                            let amount = effect.amount.unwrap_or(DEFAULT_SHAKE_AMOUNT);
+                            let (from, to) = (cut.trimmed_time(effect_start - start_seconds), cut.trimmed_time(effect_end - start_seconds));
+                            filters.push(get_shake_filter(width, height, amount, from, to));
+                        }
+                        _ => {}
+                    }
+                    break;
+                }
+            }
+        }
+    }
+    
+    // 4. Captions, each layer with an optional background image behind it
+    let has_caption = cut.caption.is_some() || cut.caption_words.as_ref().is_some_and(|w| !w.is_empty());
+    let layers: Vec<(usize, &str)> = has_caption.then_some((0, cut.caption.as_deref().unwrap_or_default())).into_iter()
+        .chain(cut.captions.iter().flatten().enumerate().map(|(i, extra)| (i + 1, extra.text.as_str())))
+        .collect();
+    for &(layer, _) in &layers {
+        let style = cut.layer_style(layer);
+        if let Some(style) = style {
+            warn_missing_font(style.font.as_deref());
+            warn_invalid_colors(&[
+                ("caption color", style.color.as_ref()),
+                ("highlight_color", style.highlight_color.as_ref()),
+                ("border_color", style.border_color.as_ref()),
+            ]);
+        }
+        if let Some(asset) = style.as_ref().and_then(|s| s.background_asset.as_deref()).filter(|asset| is_inside_dir(asset)) {
+            let asset_path = config.assets_dir.join(asset);
+            if asset_path.exists() {
+                filters.push(get_caption_background_filter(&asset_path, style, CAPTION_FONT_SIZE));
+            } else {
+                log_json("WARN", &format!("Caption background {:?} not found, drawing caption without it", asset_path), Some("caption_asset_missing"), None);
+            }
+        }
+    }
+    // Caption text goes inline, or through temp files (NUE_CAPTION_TEXTFILE) that sidestep argv
+    // quoting for long or non-Latin captions; the guard removes them once the segment is encoded
+    let mut caption_files = TempFiles::new(config.keep_temp);
+    let mut text_arg = |text: &str| -> String {
+        if !config.caption_textfile {
+            return format!("text='{}'", escape_drawtext(text));
+        }
+        let name = format!("{}_seg_{:04}_cap_{:02}.txt", temp_prefix, index, caption_files.len());
+        let path = caption_files.track(config.temp_dir.join(name));
+        if !config.dry_run {
+            if let Err(e) = fs::write(&path, escape_expansion(text)) {
+                log_json("WARN", &format!("Could not write caption file, drawing text inline: {}", e), Some("caption_textfile_failed"), path.to_str());
+                return format!("text='{}'", escape_drawtext(text));
+            }
+        }
+        format!("textfile='{}':reload=1", escape_filter_value(&path.to_string_lossy()))
+    };
+    // Burned in from one .ass file during the final mix instead
+    for &(layer, text) in layers.iter().filter(|_| !analysis.uses_libass()) {
+        let style = cut.layer_style(layer);
+        let max_chars = style.as_ref()
+            .and_then(|s| s.max_chars_per_line)
+            .unwrap_or(DEFAULT_MAX_CHARS_PER_LINE);
+        if let Some(words) = cut.caption_words.as_ref().filter(|w| layer == 0 && !w.is_empty()) {
+            warn_mixed_emoji(&words.iter().map(|(w, _)| w.as_str()).collect::<Vec<_>>().join(" "));
+            filters.extend(get_karaoke_filters(words, style, max_chars, CAPTION_FONT_SIZE, CAPTION_LINE_HEIGHT, duration, &mut text_arg));
+            continue;
+        }
+        let (font, color, box_conf, outline, y) = get_drawtext_config(style);
+        let lines = wrap_caption(text, max_chars);
+        warn_mixed_emoji(text);
+        
+        let alpha = get_caption_alpha(style, duration).unwrap_or_default();
+        
+        // One drawtext per line so each line is centered on its own.
+        // Show caption for the entire segment duration
+        for (line_index, line) in lines.iter().enumerate() {
+            let (font, line) = select_font(line, &font);
+            let line_y = get_caption_line_y(&y, style, line_index, lines.len(), CAPTION_LINE_HEIGHT);
+            let x = get_caption_x(style, "(w-text_w)/2", false);
+            let (x, line_y) = get_caption_motion(style, &x, &line_y);
+            let drawtext = format!(
+                "drawtext=fontfile='{}':{}:fontcolor={}:fontsize={}:x={}:y={}{}{}{}:enable='between(t,0,{})'",
+                escape_filter_value(font), text_arg(&line), color, CAPTION_FONT_SIZE, x, line_y, outline, box_conf, alpha, duration
+            );
+            filters.push(drawtext);
+        }
+    }
+    
+    // 4b. Watermark on top of everything
+    if let Some(watermark) = watermark_path(analysis, config) {
+        let position = analysis.watermark_position.as_deref().unwrap_or("top_right");
+        let opacity = analysis.watermark_opacity.unwrap_or(DEFAULT_WATERMARK_OPACITY);
+        filters.push(get_watermark_filter(&watermark, position, opacity, width));
+    }
+
+    let video_filter = filters.join(",");
+    
+    // 5. Per-cut audio gain (replaces the global voice boost when any cut sets one)
+    let audio_volume = if analysis.has_cut_volumes() {
+        let volume = cut.volume.unwrap_or(1.0);
+        log_json("INFO", &format!("Segment {} audio volume {} (global boost disabled)", index, volume), Some("cut_volume"), None);
+        Some(volume)
+    } else {
+        None
+    };
+    
+    // 6. Speed window (slowmo/speedup) changes the segment's rendered length
+    let speed = speed_window(cut, start_seconds, end_seconds, analysis);
+    let content = match speed {
+        Some((from, to, factor)) => duration - (to - from) + (to - from) / factor,
+        None => duration,
+    };
+
+    // 7. Freeze frames hold the finished (captioned, retimed) first/last frame; the -t cap
+    // below covers the held time too
+    let hold = (cut.freeze_duration() > 0.0).then(|| {
+        get_freeze_filters(content, cut.freeze_start.unwrap_or(0.0), cut.freeze_end.unwrap_or(0.0))
+    });
+    let rendered = content + cut.freeze_duration();
+    
+    // Run ffmpeg to extract and process this segment
+    let encoder = config.video_encoder;
+    let settings = EncodeSettings::from_analysis(analysis);
+    if let Some((from, to, factor)) = speed {
+        log_json("INFO", &format!("Segment {} speed x{} from {:.3}s to {:.3}s", index, factor, from, to), Some("speed_effect"), None);
+    }
+    let segment = SegmentCommand {
+        input: video_path,
+        start: start_seconds,
+        still_fps: cut.is_still().then(|| source.fps.unwrap_or(DEFAULT_STILL_FPS)),
+        has_audio: source.has_audio,
+        duration,
+        rendered,
+        video_filter: &video_filter,
+        audio_trim: trim.as_ref().map(|(_, audio)| audio.as_str()),
+        audio_volume,
+        hold: hold.as_ref(),
+        speed,
+        encoder,
+        settings: &settings,
+    };
+
+    // Two-pass: the first pass only writes the x264 stats log, which the guard removes
+    let mut passlog = TempFiles::new(config.keep_temp);
+    let passlog_prefix = segment_path.with_extension("passlog");
+    let two_pass = settings.two_pass(encoder);
+    if two_pass {
+        let log_base = passlog_prefix.to_string_lossy().into_owned();
+        passlog.track(PathBuf::from(format!("{}-0.log", log_base)));
+        passlog.track(PathBuf::from(format!("{}-0.log.mbtree", log_base)));
+    } else if settings.target_bitrate.is_some() {
+        log_json("WARN", &format!("Segment {}: {} has no two-pass mode, encoding target_bitrate in one pass", index, encoder.codec_for(settings.format)), Some("two_pass_unsupported"), None);
+    }
+    let first_pass = two_pass.then(|| ffmpeg_command(build_segment_args(&segment, Pass::First(&passlog_prefix), &segment_path)));
+    let pass = if two_pass { Pass::Second(&passlog_prefix) } else { Pass::Single };
+    let mut cmd = ffmpeg_command(build_segment_args(&segment, pass, &segment_path));
+    
+    if config.dry_run {
+        // Path is returned as if rendered so the rest of the pipeline can be inspected
+        if let Some(pass1) = &first_pass {
+            log_dry_run(pass1, &format!("Segment {} pass 1 (not run)", index));
+        }
+        log_dry_run(&cmd, &format!("Segment {} (not written)", index));
+        return Ok(segment_path);
+    }
+    
+    if let Some(mut pass1) = first_pass {
+        let label = format!("Segment {} pass 1", index);
+        let output = run_with_retry(&label, config.ffmpeg_retries, || run_with_progress(&mut pass1, rendered, &label))?;
+        if !output.status.success() {
+            return Err(FfmpegError::from_output(format!("Segment {} pass 1 failed", index), &output).into());
+        }
+    }
+    
+    let label = format!("Segment {}", index);
+    let output = run_with_retry(&label, config.ffmpeg_retries, || run_with_progress(&mut cmd, rendered, &label))?;
+    
+    if !output.status.success() {
+        return Err(FfmpegError::from_output(format!("Segment {} failed", index), &output).into());
+    }
+    
+    log_json("INFO", &format!("Segment {} complete", index), Some("segment_done"), None);
+    Ok(segment_path)
+}
+
+// (start, duration) of each cut on the final timeline. Each cut starts where the previous
+// one ended, pulled back by the overlap of its incoming transition (matching
+// stitch_with_transitions).
+fn timeline_spans(cuts: &[Cut], analysis: &Analysis, overlaps: &[f64]) -> Vec<(f64, f64)> {
+    let (offsets, total) = segment_offsets(cuts, analysis);
+    let mut shift = 0.0;
+    (0..cuts.len()).map(|i| {
+        shift += overlaps[i];
+        (offsets[i] - shift, offsets.get(i + 1).unwrap_or(&total) - offsets[i])
+    }).collect()
+}
+
+// (layer, text) of each non-blank caption on the cut; see Cut::layer_style
+fn caption_layers(cut: &Cut) -> Vec<(usize, String)> {
+    let main = match (&cut.caption_words, &cut.caption) {
+        (Some(words), _) if !words.is_empty() => Some(words.iter().map(|(w, _)| w.as_str()).collect::<Vec<_>>().join(" ")),
+        (_, Some(caption)) => Some(caption.clone()),
+        _ => None,
+    };
+    let extra = cut.captions.iter().flatten().map(|c| Some(c.text.clone()));
+    std::iter::once(main).chain(extra).enumerate()
+        .filter_map(|(layer, text)| Some((layer, text.filter(|t| !t.trim().is_empty())?)))
+        .collect()
+}
+
+// One cue per caption layer of each cut
+fn caption_cues(cuts: &[Cut], analysis: &Analysis, overlaps: &[f64]) -> Vec<Cue> {
+    let mut cues: Vec<Cue> = Vec::new();
+    for (i, (cut, (start, duration))) in cuts.iter().zip(timeline_spans(cuts, analysis, overlaps)).enumerate() {
+        for (layer, text) in caption_layers(cut) {
+            // Hand over at the start of a transition rather than stacking two captions
+            if let Some(previous) = cues.iter_mut().rev().find(|c| c.cut < i && c.layer == layer).filter(|c| c.end > start) {
+                previous.end = start;
+            }
+            cues.push(Cue { cut: i, layer, start, end: start + duration, text });
+        }
+    }
+    cues
+}
+
+// One chapter per cut, from its start to the next cut's (a transition belongs to the cut it
+// leads into) and the last to the end of the video. Uncaptioned cuts are titled by number.
+fn chapter_marks(cuts: &[Cut], analysis: &Analysis, overlaps: &[f64], total_duration: f64) -> Vec<Cue> {
+    let spans = timeline_spans(cuts, analysis, overlaps);
+    cuts.iter().enumerate().map(|(i, cut)| {
+        let start = spans[i].0;
+        let end = spans.get(i + 1).map(|(next, _)| *next).unwrap_or(total_duration);
+        let text = caption_layers(cut).into_iter().next().map(|(_, text)| text).unwrap_or_else(|| format!("Cut {}", i + 1));
+        Cue { cut: i, layer: 0, start, end, text }
+    }).collect()
+}
+
+// The first slowmo/speedup effect starting inside the cut, as (from, to, speed) with
+// from/to relative to the cut start and clamped to it. Effect times are on the clock of
+// original_filename, so cuts with their own source never get one.
+fn speed_window(cut: &Cut, start_seconds: f64, end_seconds: f64, analysis: &Analysis) -> Option<(f64, f64, f64)> {
+    if cut.source.is_some() {
+        return None;
+    }
+    let time_format = analysis.time_format;
+    analysis.visual_effects.as_ref()?.iter().find_map(|effect| {
+        let factor = get_speed_factor(&effect.effect_type, effect.speed.as_deref())?;
+        let effect_start = parse_time(&effect.start, time_format).ok()?;
+        if effect_start < start_seconds || effect_start >= end_seconds {
+            return None;
+        }
+        let effect_end = parse_time(&effect.end, time_format).unwrap_or(end_seconds).min(end_seconds);
+        (effect_end > effect_start).then_some((effect_start - start_seconds, effect_end - start_seconds, factor))
+    })
+}
+
+// A cut's start/end in seconds, with errors saying which cut and which field was bad
+fn cut_times(index: usize, cut: &Cut, analysis: &Analysis) -> Result<(f64, f64)> {
+    let start = parse_time(&cut.start_time, analysis.time_format)
+        .map_err(|e| anyhow::anyhow!("Cut {}: bad start_time: {}", index, e))?;
+    let end = parse_time(&cut.end_time, analysis.time_format)
+        .map_err(|e| anyhow::anyhow!("Cut {}: bad end_time: {}", index, e))?;
+    Ok((start, end))
+}
+
+// Length of a cut once rendered, i.e. after any speed window and freeze frames are applied
+fn rendered_duration(cut: &Cut, analysis: &Analysis) -> Result<f64> {
+    let start = parse_time(&cut.start_time, analysis.time_format)?;
+    let end = parse_time(&cut.end_time, analysis.time_format)?;
+    let content = match speed_window(cut, start, end, analysis) {
+        Some((from, to, factor)) => (end - start) - (to - from) + (to - from) / factor,
+        None => end - start - cut.silence_removed(),
+    };
+    Ok(content + cut.freeze_duration())
+}
+
+// The cut as it will be rendered, plus the file its segment reads and that file's probe info.
+// Stills become a 0..duration cut over the looped image (no audio); video cuts are fitted
+// to the length of their source, which is probed once per file into `probed`.
+fn prepare_cut(
+    index: usize,
+    cut: &Cut,
+    video_path: &Path,
+    source: &SourceInfo,
+    probed: &mut HashMap<PathBuf, SourceInfo>,
+    analysis: &Analysis,
+    config: &Config,
+) -> Result<(Cut, PathBuf, SourceInfo)> {
+    if let Some(source) = cut.source.as_deref().filter(|source| !is_inside_dir(source)) {
+        return Err(anyhow::anyhow!("Cut {}: source '{}' must be a relative path inside the raw dir", index, source));
+    }
+    let Some(path) = cut_source_path(cut, config) else {
+        return Ok((fit_to_source(index, cut, source, analysis)?, video_path.to_path_buf(), source.clone()));
+    };
+    if !path.exists() {
+        return Err(anyhow::anyhow!("Cut {}: source not found: {:?}", index, path));
+    }
+
+    if !cut.is_still() {
+        let info = probed.entry(path.clone()).or_insert_with(|| probe_source(&path)).clone();
+        return Ok((fit_to_source(index, cut, &info, analysis)?, path, info));
+    }
+
+    let duration = cut.duration.ok_or_else(|| anyhow::anyhow!("Cut {}: still source needs a duration", index))?;
+    let mut still = cut.clone();
+    still.start_time = format_time(0.0, analysis.time_format);
+    still.end_time = format_time(duration, analysis.time_format);
+    let info = SourceInfo { has_audio: false, fps: source.fps, ..SourceInfo::default() };
+    log_json("INFO", &format!("Cut {}: still image for {:.3}s", index, duration), Some("still_cut"), path.to_str());
+    Ok((still, path, info))
+}
+
+// A cut's own source file (image or video), relative to RAW_DIR
+fn cut_source_path(cut: &Cut, config: &Config) -> Option<PathBuf> {
+    cut.source.as_ref().map(|source| config.raw_dir.join(source))
+}
+
+// Past its end ffmpeg renders a short or frozen segment while the timeline math still
+// counts the full cut, so the audio fade lands early. Cuts that start after the end of the
+// source are rejected; cuts that run past it end at the source's end.
+fn fit_to_source(index: usize, cut: &Cut, source: &SourceInfo, analysis: &Analysis) -> Result<Cut> {
+    let (start, end) = cut_times(index, cut, analysis)?;
+    let Some(source_duration) = source.duration else {
+        return Ok(cut.clone());
+    };
+    if start >= source_duration {
+        return Err(anyhow::anyhow!(
+            "Cut {} starts at {:.3}s, past the end of the source ({:.3}s)", index, start, source_duration
+        ));
+    }
+    let mut fitted = cut.clone();
+    if end > source_duration + 0.001 {
+        fitted.end_time = format_time(source_duration, analysis.time_format);
+        log_json("WARN", &format!(
+            "Cut {} ends at {:.3}s, past the end of the source; clamped to {}", index, end, fitted.end_time
+        ), Some("cut_clamped"), None);
+    }
+    Ok(fitted)
+}
+
+// Cuts shorter than min_cut_duration: padded out to it (past the end while the source lasts,
+// then back from the start), or None to skip them. Zero-length cuts are left for
+// process_single_segment to reject.
+fn lengthen_short_cut(index: usize, cut: &Cut, source: &SourceInfo, analysis: &Analysis) -> Result<Option<Cut>> {
+    let minimum = analysis.min_cut_duration.unwrap_or(DEFAULT_MIN_CUT_DURATION);
+    let (start, end) = cut_times(index, cut, analysis)?;
+    let duration = end - start;
+    if duration <= 0.0 || duration >= minimum {
+        return Ok(Some(cut.clone()));
+    }
+    if analysis.short_cut.as_deref() == Some("skip") {
+        log_json("WARN", &format!("Cut {} lasts {:.3}s, under min_cut_duration {:.3}s; skipped", index, duration, minimum), Some("short_cut_skipped"), None);
+        return Ok(None);
+    }
+    let new_end = (start + minimum).min(source.duration.unwrap_or(f64::MAX));
+    let new_start = (new_end - minimum).max(0.0);
+    let mut padded = cut.clone();
+    padded.start_time = format_time(new_start, analysis.time_format);
+    padded.end_time = format_time(new_end, analysis.time_format);
+    log_json("WARN", &format!(
+        "Cut {} lasts {:.3}s, under min_cut_duration {:.3}s; padded to {}-{}", index, duration, minimum, padded.start_time, padded.end_time
+    ), Some("short_cut_padded"), None);
+    Ok(Some(padded))
+}
+
+// trim_silence: record the cut's pauses (less SILENCE_MARGIN on each side) and move its
+// karaoke words onto the trimmed clock. Stills, silent sources and cuts with a speed window
+// are left as they are, as are cuts whose detection fails or that trimming would leave
+// shorter than min_cut_duration.
+fn find_silences(index: usize, mut cut: Cut, input: &Path, source: &SourceInfo, analysis: &Analysis) -> Cut {
+    if !analysis.trim_silence.unwrap_or(false) || cut.is_still() || !source.has_audio {
+        return cut;
+    }
+    let Ok((start, end)) = cut_times(index, &cut, analysis) else {
+        return cut;
+    };
+    if speed_window(&cut, start, end, analysis).is_some() {
+        return cut;
+    }
+    let threshold = analysis.silence_threshold.unwrap_or(DEFAULT_SILENCE_THRESHOLD);
+    let min_silence = analysis.silence_min_duration.unwrap_or(DEFAULT_SILENCE_MIN_DURATION);
+    let silences = match detect_silences(input, start, end - start, threshold, min_silence) {
+        Ok(silences) => silences,
+        Err(e) => {
+            log_json("WARN", &format!("Cut {}: silence detection failed, keeping the cut whole: {}", index, e), Some("silence_trim_failed"), input.to_str());
+            return cut;
+        }
+    };
+    cut.silences = silences.into_iter()
+        .map(|(from, to)| (from + SILENCE_MARGIN, to - SILENCE_MARGIN))
+        .filter(|(from, to)| to > from)
+        .collect();
+    if cut.silences.is_empty() {
+        return cut;
+    }
+    let removed = cut.silence_removed();
+    let minimum = analysis.min_cut_duration.unwrap_or(DEFAULT_MIN_CUT_DURATION);
+    if end - start - removed < minimum.max(SILENCE_MARGIN * 2.0) {
+        log_json("WARN", &format!("Cut {} is nearly all silence, keeping it whole", index), Some("silence_trim_skipped"), None);
+        cut.silences.clear();
+        return cut;
+    }
+    let retimed = cut.caption_words.as_ref().map(|words| {
+        words.iter().map(|(word, at)| (word.clone(), cut.trimmed_time(*at))).collect()
+    });
+    cut.caption_words = retimed;
+    log_json("INFO", &format!("Cut {}: trimming {} pauses ({:.3}s)", index, cut.silences.len(), removed), Some("silence_trim"), None);
+    cut
+}
+
+// Where each cut starts on the timeline and the summed length, laid end to end without
+// transition overlap. Cuts with unparseable times count as zero length.
+fn segment_offsets(cuts: &[Cut], analysis: &Analysis) -> (Vec<f64>, f64) {
+    let mut offsets = Vec::with_capacity(cuts.len());
+    let mut total = 0.0;
+    for cut in cuts {
+        offsets.push(total);
+        total += rendered_duration(cut, analysis).unwrap_or(0.0);
+    }
+    (offsets, total)
+}
+
+fn total_duration(cuts: &[Cut], analysis: &Analysis) -> f64 {
+    segment_offsets(cuts, analysis).1
+}
+
+// Overlap of each cut's incoming transition: 0 for the first cut and hard cuts, else the
+// cut's transition_duration or the global one. Clamped to the shorter adjoining segment,
+// since a longer xfade runs off the end of its input and corrupts the join.
+fn transition_overlaps(cuts: &[Cut], analysis: &Analysis) -> Vec<f64> {
+    let default = analysis.transition_duration.unwrap_or(DEFAULT_TRANSITION_DURATION);
+    let durations: Vec<f64> = cuts.iter().map(|cut| rendered_duration(cut, analysis).unwrap_or(0.0)).collect();
+    cuts.iter().enumerate().map(|(i, cut)| {
+        if i == 0 || cut.transition_type.is_none() {
+            return 0.0;
+        }
+        let requested = cut.transition_duration.unwrap_or(default);
+        let limit = durations[i - 1].min(durations[i]);
+        if requested > limit {
+            log_json("WARN", &format!(
+                "Transition into cut {} lasts {:.3}s, longer than an adjoining segment; clamped to {:.3}s", i, requested, limit
+            ), Some("transition_clamped"), None);
+            return limit;
+        }
+        requested
+    }).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Cuts only need their times; every one gets the required "filter"
+    fn analysis(json: serde_json::Value) -> Analysis {
+        let mut base = serde_json::json!({ "original_filename": "in.mp4", "time_format": "seconds" });
+        base.as_object_mut().unwrap().extend(json.as_object().unwrap().clone());
+        for cut in base["cuts"].as_array_mut().unwrap() {
+            cut["filter"] = "none".into();
+        }
+        serde_json::from_value(base).unwrap()
+    }
+
+    fn approx(actual: f64, expected: f64) {
+        assert!((actual - expected).abs() < 1e-9, "expected {}, got {}", expected, actual);
+    }
+
+    fn check(analysis: &Analysis, offsets: &[f64], total: f64) {
+        let (actual, actual_total) = segment_offsets(&analysis.cuts, analysis);
+        assert_eq!(actual.len(), offsets.len());
+        for (&actual, &expected) in actual.iter().zip(offsets) {
+            approx(actual, expected);
+        }
+        approx(actual_total, total);
+        approx(total_duration(&analysis.cuts, analysis), total);
+    }
+
+    #[test]
+    fn plain_cuts_add_up() {
+        let analysis = analysis(serde_json::json!({ "cuts": [
+            { "start_time": "1", "end_time": "4" },
+            { "start_time": "10", "end_time": "12.5" },
+            { "start_time": "20", "end_time": "21" },
+        ] }));
+        check(&analysis, &[0.0, 3.0, 5.5], 6.5);
+    }
+
+    #[test]
+    fn speed_window_retimes_its_cut() {
+        // 2s of the first cut at half speed play for 4s
+        let analysis = analysis(serde_json::json!({
+            "cuts": [
+                { "start_time": "0", "end_time": "4" },
+                { "start_time": "10", "end_time": "12" },
+            ],
+            "visual_effects": [{ "start": "1", "end": "3", "type": "slowmo" }],
+        }));
+        check(&analysis, &[0.0, 6.0], 8.0);
+    }
+
+    #[test]
+    fn freeze_holds_lengthen_their_cut() {
+        let analysis = analysis(serde_json::json!({ "cuts": [
+            { "start_time": "0", "end_time": "2", "freeze_start": 0.5, "freeze_end": 1.0 },
+            { "start_time": "5", "end_time": "6" },
+        ] }));
+        check(&analysis, &[0.0, 3.5], 4.5);
+    }
+
+    #[test]
+    fn unparseable_cut_counts_as_zero() {
+        let analysis = analysis(serde_json::json!({ "cuts": [
+            { "start_time": "0", "end_time": "2" },
+            { "start_time": "soon", "end_time": "3" },
+            { "start_time": "5", "end_time": "6" },
+        ] }));
+        check(&analysis, &[0.0, 2.0, 2.0], 3.0);
+    }
+}
@@ -0,0 +1,131 @@
+use log::info;
+use serde::Deserialize;
+use std::path::{Path, PathBuf};
+
+use crate::analysis::SeEvent;
+use crate::logging::log_json;
+
+pub(crate) const DEFAULT_SE_FILE: &str = "default_se.wav";
+pub(crate) const DEFAULT_SE_VOLUME: f64 = 0.8;
+
+pub(crate) const SE_MAP_FILE: &str = "se_map.json";
+
+// One tag -> file mapping. Rules match when the lowercased tag contains `pattern`,
+// or equals it when `exact` is set.
+#[derive(Debug, Clone, Deserialize)]
+pub struct SeRule {
+    pub pattern: String,
+    pub file: String,
+    pub exact: Option<bool>,
+}
+
+impl SeRule {
+    fn matches(&self, tag: &str) -> bool {
+        let pattern = self.pattern.to_lowercase();
+        if self.exact.unwrap_or(false) {
+            tag == pattern
+        } else {
+            tag.contains(&pattern)
+        }
+    }
+}
+
+// Tag to SE file mapping, loaded from se_map.json in the SE directory when present:
+// {"rules": [{"pattern": "glass_break", "file": "GLASS.wav", "exact": true}], "default": "SYNTH_DON.wav"}
+// Rules are tried in order; the first match wins.
+#[derive(Debug, Clone, Deserialize)]
+pub struct SeMap {
+    pub rules: Vec<SeRule>,
+    pub default: String,
+}
+
+impl Default for SeMap {
+    // "serious", "correct", "impact" and anything unknown map to the DON hit.
+    fn default() -> Self {
+        let rule = |pattern: &str, file: &str| SeRule { pattern: pattern.to_string(), file: file.to_string(), exact: None };
+        SeMap {
+            rules: vec![rule("funny", "SYNTH_WHOOSH.wav"), rule("whoosh", "SYNTH_WHOOSH.wav")],
+            default: "SYNTH_DON.wav".to_string(),
+        }
+    }
+}
+
+impl SeMap {
+    // Read se_map.json from the SE directory, falling back to the built-in mapping
+    pub fn load(se_dir: &Path) -> Self {
+        let path = se_dir.join(SE_MAP_FILE);
+        let Ok(content) = std::fs::read_to_string(&path) else {
+            return SeMap::default();
+        };
+        match serde_json::from_str(&content) {
+            Ok(map) => {
+                log_json("INFO", "Loaded SE map", Some("se_map_loaded"), path.to_str());
+                map
+            }
+            Err(e) => {
+                log_json("WARN", &format!("Invalid SE map, using built-in mapping: {}", e), Some("se_map_invalid"), path.to_str());
+                SeMap::default()
+            }
+        }
+    }
+
+    fn rule_for(&self, tag: &str) -> Option<&str> {
+        self.rules.iter()
+            .find(|rule| rule.matches(tag))
+            .map(|rule| rule.file.as_str())
+    }
+
+    fn file_for(&self, tag: &str) -> &str {
+        self.rule_for(tag).unwrap_or(&self.default)
+    }
+}
+
+pub(crate) fn get_se_file(tag: &str, se_dir: &Path, se_map: &SeMap) -> Option<PathBuf> {
+    let tag_lower = tag.to_lowercase();
+    
+    // V13 LOGIC: SYNTHETIC SAFE SOUNDS
+    // User Feedback: "Get safe sounds".
+    // Action: We generated pure synthetic WAVs (Pink Noise Swoosh, Sine Wave Don).
+    // Zero artifacts guaranteed.
+    
+    info!("Selecting SE for tag: {}", tag_lower);
+
+    let filename = se_map.file_for(&tag_lower);
+    
+    let candidate = se_dir.join(filename);
+    if candidate.exists() {
+        log_json("INFO", &format!("Selected SAFE SE for '{}': {:?}", tag, candidate), Some("se_selection"), None);
+        return Some(candidate);
+    }
+    
+    // A missing input poisons the whole amix graph, so fall back rather than let ffmpeg fail
+    let fallback = se_dir.join(DEFAULT_SE_FILE);
+    if fallback.exists() {
+        log_json("WARN", &format!("SE {:?} missing for '{}', using fallback {:?}", candidate, tag, fallback), Some("se_fallback"), None);
+        Some(fallback)
+    } else {
+        log_json("WARN", &format!("SE {:?} and fallback {:?} missing for '{}'", candidate, fallback, tag), Some("se_missing"), None);
+        None
+    }
+}
+
+// Pick the SE for an event. A `tag` wins when it names something specific: a mapping
+// rule, or a file `{tag}.wav` in the SE directory. Otherwise the `type` heuristic applies.
+pub(crate) fn select_se_file(se: &SeEvent, se_dir: &Path, se_map: &SeMap) -> Option<PathBuf> {
+    if let Some(tag) = se.tag.as_deref().map(str::trim).filter(|t| !t.is_empty()) {
+        if se_map.rule_for(&tag.to_lowercase()).is_some() {
+            log_json("INFO", &format!("SE at {} selected by tag '{}' (se_map rule)", se.timestamp, tag), Some("se_source"), None);
+            return get_se_file(tag, se_dir, se_map);
+        }
+        // Only plain names, so a tag can't point outside the SE directory
+        let named = se_dir.join(format!("{}.wav", tag));
+        if !tag.contains(['/', '\\']) && named.exists() {
+            log_json("INFO", &format!("SE at {} selected by tag '{}': {:?}", se.timestamp, tag, named), Some("se_source"), None);
+            return Some(named);
+        }
+        log_json("INFO", &format!("SE tag '{}' matched nothing, falling back to type '{}'", tag, se.event_type), Some("se_source"), None);
+    } else {
+        log_json("INFO", &format!("SE at {} selected by type '{}'", se.timestamp, se.event_type), Some("se_source"), None);
+    }
+    get_se_file(&se.event_type, se_dir, se_map)
+}
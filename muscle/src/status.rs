@@ -0,0 +1,95 @@
+use muscle::{ffmpeg_seconds, log_json};
+use serde_json::json;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+const DEFAULT_HEARTBEAT_SECS: u64 = 60;
+
+// Liveness and throughput counters shared by the watcher loop, the HTTP trigger and
+// the heartbeat thread
+pub struct Status {
+    started: Instant,
+    seen: AtomicU64,
+    succeeded: AtomicU64,
+    failed: AtomicU64,
+}
+
+impl Status {
+    pub fn new() -> Self {
+        Status {
+            started: Instant::now(),
+            seen: AtomicU64::new(0),
+            succeeded: AtomicU64::new(0),
+            failed: AtomicU64::new(0),
+        }
+    }
+
+    // An instruction arrived (file event or HTTP request), before parsing
+    pub fn record_seen(&self) {
+        self.seen.fetch_add(1, Ordering::Relaxed);
+    }
+
+    // An instruction finished; parse and validation failures count as failed
+    pub fn record_result(&self, success: bool) {
+        let counter = if success { &self.succeeded } else { &self.failed };
+        counter.fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn processed(&self) -> u64 {
+        self.succeeded.load(Ordering::Relaxed) + self.failed.load(Ordering::Relaxed)
+    }
+
+    pub fn to_json(&self) -> serde_json::Value {
+        json!({
+            "status": "ok",
+            "uptime_secs": self.started.elapsed().as_secs(),
+            "processed": self.processed(),
+            "seen": self.seen.load(Ordering::Relaxed),
+            "succeeded": self.succeeded.load(Ordering::Relaxed),
+            "failed": self.failed.load(Ordering::Relaxed),
+            "ffmpeg_seconds": ffmpeg_seconds(),
+        })
+    }
+
+    // Prometheus text exposition format for GET /metrics
+    #[cfg(feature = "metrics")]
+    pub fn to_prometheus(&self) -> String {
+        let metrics = [
+            ("nue_files_seen_total", "counter", "Instructions received", self.seen.load(Ordering::Relaxed) as f64),
+            ("nue_files_succeeded_total", "counter", "Instructions rendered successfully", self.succeeded.load(Ordering::Relaxed) as f64),
+            ("nue_files_failed_total", "counter", "Instructions that failed", self.failed.load(Ordering::Relaxed) as f64),
+            ("nue_ffmpeg_seconds_total", "counter", "Wall time spent running ffmpeg", ffmpeg_seconds()),
+            ("nue_uptime_seconds", "gauge", "Seconds since the watcher started", self.started.elapsed().as_secs_f64()),
+        ];
+        metrics
+            .iter()
+            .map(|(name, kind, help, value)| format!("# HELP {name} {help}\n# TYPE {name} {kind}\n{name} {value}\n"))
+            .collect()
+    }
+}
+
+// Log a heartbeat with the counters every NUE_HEARTBEAT_SECS (default 60; 0 disables),
+// so a wedged watcher and the failure rate are visible from the logs alone
+pub fn spawn_heartbeat(status: Arc<Status>) {
+    let secs = std::env::var("NUE_HEARTBEAT_SECS")
+        .ok()
+        .and_then(|v| v.trim().parse().ok())
+        .unwrap_or(DEFAULT_HEARTBEAT_SECS);
+    if secs == 0 {
+        return;
+    }
+
+    std::thread::spawn(move || loop {
+        std::thread::sleep(Duration::from_secs(secs));
+        let message = format!(
+            "Heartbeat: up {}s, {} seen, {} succeeded, {} failed, {:.1}s in ffmpeg",
+            status.started.elapsed().as_secs(),
+            status.seen.load(Ordering::Relaxed),
+            status.succeeded.load(Ordering::Relaxed),
+            status.failed.load(Ordering::Relaxed),
+            ffmpeg_seconds()
+        );
+        log_json("INFO", &message, Some("heartbeat"), None);
+    });
+}
@@ -0,0 +1,70 @@
+// Sidecar subtitles and chapter metadata built from cut captions, timed on the final
+// (concatenated) timeline
+
+pub(crate) struct Cue {
+    // Index of the cut the caption belongs to
+    pub cut: usize,
+    // Caption layer within the cut (see Cut::layer_style)
+    pub layer: usize,
+    pub start: f64,
+    pub end: f64,
+    pub text: String,
+}
+
+// HH:MM:SS<sep>mmm; SRT uses a comma before the millis, WebVTT a dot
+fn format_timestamp(seconds: f64, separator: char) -> String {
+    let total_millis = (seconds.max(0.0) * 1000.0).round() as u64;
+    let (hours, rest) = (total_millis / 3_600_000, total_millis % 3_600_000);
+    let (minutes, rest) = (rest / 60_000, rest % 60_000);
+    let (secs, millis) = (rest / 1000, rest % 1000);
+    format!("{:02}:{:02}:{:02}{}{:03}", hours, minutes, secs, separator, millis)
+}
+
+pub(crate) fn to_srt(cues: &[Cue]) -> String {
+    let mut out = String::new();
+    for (i, cue) in cues.iter().enumerate() {
+        out.push_str(&format!(
+            "{}\n{} --> {}\n{}\n\n",
+            i + 1,
+            format_timestamp(cue.start, ','),
+            format_timestamp(cue.end, ','),
+            cue.text
+        ));
+    }
+    out
+}
+
+pub(crate) fn to_vtt(cues: &[Cue]) -> String {
+    let mut out = String::from("WEBVTT\n\n");
+    for cue in cues {
+        out.push_str(&format!(
+            "{} --> {}\n{}\n\n",
+            format_timestamp(cue.start, '.'),
+            format_timestamp(cue.end, '.'),
+            cue.text
+        ));
+    }
+    out
+}
+
+// ffmetadata chapters for the final mux, one per cue, in milliseconds. Title values escape
+// the characters ffmetadata treats as syntax.
+pub(crate) fn to_ffmetadata(chapters: &[Cue]) -> String {
+    let mut out = String::from(";FFMETADATA1\n");
+    for chapter in chapters {
+        let mut title = String::new();
+        for c in chapter.text.chars() {
+            if matches!(c, '=' | ';' | '#' | '\\' | '\n') {
+                title.push('\\');
+            }
+            title.push(c);
+        }
+        out.push_str(&format!(
+            "\n[CHAPTER]\nTIMEBASE=1/1000\nSTART={}\nEND={}\ntitle={}\n",
+            (chapter.start.max(0.0) * 1000.0).round() as u64,
+            (chapter.end.max(0.0) * 1000.0).round() as u64,
+            title
+        ));
+    }
+    out
+}
@@ -0,0 +1,71 @@
+use rand::Rng;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use crate::analysis::Analysis;
+
+// Per-job prefix for temp files: the input's stem plus a random token, so two renders
+// (even of the same input) never share segment or concat list names
+pub(crate) fn temp_prefix(analysis: &Analysis) -> String {
+    let stem: String = Path::new(&analysis.original_filename)
+        .file_stem()
+        .map(|s| s.to_string_lossy().into_owned())
+        .unwrap_or_default()
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() || c == '-' || c == '_' { c } else { '_' })
+        .collect();
+    format!("{}_{:08x}", stem, rand::thread_rng().gen::<u32>())
+}
+
+pub(crate) fn segment_temp_path(temp_dir: &Path, prefix: &str, index: usize) -> PathBuf {
+    temp_dir.join(format!("{}_seg_{:04}.mp4", prefix, index))
+}
+
+// Paths held by every live TempFiles, for a shutdown that exits without running their Drop
+static LIVE: Mutex<Vec<PathBuf>> = Mutex::new(Vec::new());
+
+// Removes the temp files of renders still in flight; see process::abort_renders
+pub(crate) fn remove_live_temp_files() {
+    for path in LIVE.lock().unwrap_or_else(|e| e.into_inner()).drain(..) {
+        let _ = fs::remove_file(path);
+    }
+}
+
+// Removes every tracked path when dropped, so temp files go away however the render exits.
+// Paths are tracked before they're written; missing files are ignored. With `keep` set
+// (NUE_KEEP_TEMP) nothing is removed.
+pub(crate) struct TempFiles {
+    paths: Vec<PathBuf>,
+    keep: bool,
+}
+
+impl TempFiles {
+    pub fn new(keep: bool) -> Self {
+        TempFiles { paths: Vec::new(), keep }
+    }
+
+    pub fn track(&mut self, path: PathBuf) -> PathBuf {
+        if !self.keep {
+            LIVE.lock().unwrap_or_else(|e| e.into_inner()).push(path.clone());
+        }
+        self.paths.push(path.clone());
+        path
+    }
+
+    pub fn len(&self) -> usize {
+        self.paths.len()
+    }
+}
+
+impl Drop for TempFiles {
+    fn drop(&mut self) {
+        if self.keep {
+            return;
+        }
+        LIVE.lock().unwrap_or_else(|e| e.into_inner()).retain(|live| !self.paths.contains(live));
+        for path in &self.paths {
+            let _ = fs::remove_file(path);
+        }
+    }
+}
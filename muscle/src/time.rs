@@ -0,0 +1,172 @@
+use anyhow::Result;
+use std::cell::RefCell;
+use std::collections::HashSet;
+
+use crate::analysis::TimeFormat;
+use crate::logging::log_json;
+
+// One numeric field of a timestamp. Errors name the field and quote the whole timestamp,
+// so a typo like "00:1o:05" is easy to find.
+fn parse_field(value: &str, name: &str, time_str: &str) -> Result<f64> {
+    let parsed = value.trim().parse::<f64>().ok().filter(|v| v.is_finite())
+        .ok_or_else(|| anyhow::anyhow!("Invalid timestamp '{}': {} '{}' is not a number", time_str, name, value))?;
+    if parsed < 0.0 {
+        return Err(anyhow::anyhow!("Invalid timestamp '{}': {} '{}' is negative", time_str, name, value));
+    }
+    Ok(parsed)
+}
+
+// Seconds for a timestamp. A value without colons is plain seconds whatever the format
+// (JSON numbers arrive that way); time_format only decides how "a:b:c" is read.
+pub fn parse_time(time_str: &str, format: Option<TimeFormat>) -> Result<f64> {
+    let parts: Vec<&str> = time_str.split(':').collect();
+    
+    match format {
+        _ if parts.len() == 1 => parse_field(time_str, "seconds", time_str),
+        Some(TimeFormat::Seconds) => parse_field(time_str, "seconds", time_str),
+        Some(TimeFormat::Hms) => {
+            if parts.len() != 3 {
+                return Err(anyhow::anyhow!("Expected HH:MM:SS, got '{}'", time_str));
+            }
+            let hours = parse_field(parts[0], "hours", time_str)?;
+            let minutes = parse_field(parts[1], "minutes", time_str)?;
+            let seconds = parse_field(parts[2], "seconds", time_str)?;
+            if minutes >= 60.0 || seconds >= 60.0 {
+                return Err(anyhow::anyhow!("Out of range HH:MM:SS value '{}'", time_str));
+            }
+            Ok(hours * 3600.0 + minutes * 60.0 + seconds)
+        }
+        Some(TimeFormat::MsMillis) => {
+            if parts.len() != 3 {
+                return Err(anyhow::anyhow!("Expected MM:SS:MMM, got '{}'", time_str));
+            }
+            let minutes = parse_field(parts[0], "minutes", time_str)?;
+            let seconds = parse_field(parts[1], "seconds", time_str)?;
+            let millis = parse_field(parts[2], "milliseconds", time_str)?;
+            if seconds >= 60.0 || millis >= 1000.0 {
+                return Err(anyhow::anyhow!("Out of range MM:SS:MMM value '{}'", time_str));
+            }
+            Ok(minutes * 60.0 + seconds + millis / 1000.0)
+        }
+        // A fractional seconds suffix ("HH:MM:SS.fff") only makes sense as hours:minutes:seconds,
+        // so it skips the heuristic and gets the strict range checks
+        None if parts.len() == 3 && parts[2].contains('.') => parse_time(time_str, Some(TimeFormat::Hms)),
+        None if parts.len() == 3 => {
+            let first = parse_field(parts[0], "field 1", time_str)?;
+            let second = parse_field(parts[1], "field 2", time_str)?;
+            let third = parse_field(parts[2], "field 3", time_str)?;
+            
+            // Intelligently detect format:
+            // If third field > 59, it's milliseconds (MM:SS:MMM format)
+            // If third field <= 59, it's seconds (HH:MM:SS format)
+            // Either guess can be wrong, so every value read this way is flagged.
+            if third > 59.0 {
+                warn_ambiguous(time_str, "MM:SS:MMM");
+                // MM:SS:MMM format: minutes:seconds:milliseconds
+                Ok(first * 60.0 + second + third / 1000.0)
+            } else {
+                warn_ambiguous(time_str, if second > 59.0 { "HH:MM:SS (middle field above 59)" } else { "HH:MM:SS" });
+                // HH:MM:SS format: hours:minutes:seconds
+                Ok(first * 3600.0 + second * 60.0 + third)
+            }
+        }
+        None => Err(anyhow::anyhow!("Expected seconds or a:b:c timestamp, got '{}'", time_str)),
+    }
+}
+
+thread_local! {
+    // Timestamps already flagged for this job; validation and the render parse each one
+    // several times over
+    static WARNED: RefCell<HashSet<String>> = RefCell::new(HashSet::new());
+}
+
+// Called whenever a job starts so every job flags its own guessed timestamps
+pub(crate) fn reset_ambiguous_warnings() {
+    WARNED.with(|warned| warned.borrow_mut().clear());
+}
+
+fn warn_ambiguous(time_str: &str, reading: &str) {
+    if WARNED.with(|warned| warned.borrow_mut().insert(time_str.to_string())) {
+        log_json("WARN", &format!("Ambiguous timestamp '{}' interpreted as {}; set time_format to make this explicit", time_str, reading), Some("ambiguous_timestamp"), None);
+    }
+}
+
+// Inverse of parse_time: seconds written in a form parse_time reads back the same way
+pub(crate) fn format_time(seconds: f64, format: Option<TimeFormat>) -> String {
+    let millis = (seconds.max(0.0) * 1000.0).round() as u64;
+    match format {
+        Some(TimeFormat::Hms) => format!(
+            "{:02}:{:02}:{:02}.{:03}",
+            millis / 3_600_000, millis / 60_000 % 60, millis / 1000 % 60, millis % 1000
+        ),
+        Some(TimeFormat::MsMillis) => format!("{:02}:{:02}:{:03}", millis / 60_000, millis / 1000 % 60, millis % 1000),
+        Some(TimeFormat::Seconds) | None => format!("{:.3}", millis as f64 / 1000.0),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn approx(actual: f64, expected: f64) {
+        assert!((actual - expected).abs() < 1e-9, "expected {}, got {}", expected, actual);
+    }
+
+    #[test]
+    fn dotted_seconds_are_hms() {
+        approx(parse_time("00:01:02.500", None).unwrap(), 62.5);
+        approx(parse_time("01:00:00.250", None).unwrap(), 3600.25);
+        approx(parse_time("00:00:59.999", None).unwrap(), 59.999);
+    }
+
+    #[test]
+    fn dotted_seconds_skip_the_heuristic() {
+        // Without the dot, a middle field above 59 is only warned about; with it the value is rejected
+        assert!(parse_time("00:75:02.500", None).is_err());
+        assert!(parse_time("00:01:60.000", None).is_err());
+    }
+
+    #[test]
+    fn explicit_hms_accepts_fractions() {
+        approx(parse_time("00:01:02.5", Some(TimeFormat::Hms)).unwrap(), 62.5);
+        assert!(parse_time("01:02", Some(TimeFormat::Hms)).is_err());
+    }
+
+    #[test]
+    fn colon_milliseconds() {
+        approx(parse_time("01:02:500", None).unwrap(), 62.5);
+        approx(parse_time("01:02:500", Some(TimeFormat::MsMillis)).unwrap(), 62.5);
+        approx(parse_time("00:00:005", Some(TimeFormat::MsMillis)).unwrap(), 0.005);
+        assert!(parse_time("00:60:000", Some(TimeFormat::MsMillis)).is_err());
+    }
+
+    #[test]
+    fn whole_seconds_hms() {
+        approx(parse_time("00:01:02", None).unwrap(), 62.0);
+        approx(parse_time("01:02:03", Some(TimeFormat::Hms)).unwrap(), 3723.0);
+    }
+
+    #[test]
+    fn plain_seconds() {
+        approx(parse_time("12.5", None).unwrap(), 12.5);
+        approx(parse_time("12.5", Some(TimeFormat::Seconds)).unwrap(), 12.5);
+        approx(parse_time("12.5", Some(TimeFormat::Hms)).unwrap(), 12.5);
+        approx(parse_time("5", Some(TimeFormat::MsMillis)).unwrap(), 5.0);
+    }
+
+    #[test]
+    fn rejects_typos_and_negatives_with_context() {
+        let error = parse_time("00:1o:05", None).unwrap_err().to_string();
+        assert!(error.contains("'00:1o:05'") && error.contains("'1o'"), "{}", error);
+        assert!(parse_time("-5", None).unwrap_err().to_string().contains("negative"));
+        assert!(parse_time("00:-01:05", Some(TimeFormat::Hms)).is_err());
+        assert!(parse_time("inf", Some(TimeFormat::Seconds)).is_err());
+    }
+
+    #[test]
+    fn format_time_round_trips() {
+        for format in [None, Some(TimeFormat::Hms), Some(TimeFormat::MsMillis), Some(TimeFormat::Seconds)] {
+            approx(parse_time(&format_time(62.5, format), format).unwrap(), 62.5);
+        }
+    }
+}
@@ -0,0 +1,111 @@
+#[cfg(feature = "s3")]
+use anyhow::Result;
+#[cfg(feature = "s3")]
+use std::path::Path;
+use std::path::PathBuf;
+
+use crate::logging::log_json;
+
+// Where finished renders are uploaded. Read from NUE_S3_* env vars; credentials come from
+// the usual AWS environment/profile chain.
+#[derive(Debug, Clone)]
+pub struct S3Config {
+    pub bucket: String,
+    // Custom endpoint for S3-compatible stores (MinIO, R2, ...); implies path-style URLs
+    pub endpoint: Option<String>,
+    pub region: Option<String>,
+    // Key prefix, e.g. "renders/"
+    pub prefix: String,
+    // Base URL recorded in the manifest instead of the s3:// URI, e.g. a CDN in front of the bucket
+    pub public_url: Option<String>,
+}
+
+impl S3Config {
+    pub fn from_env() -> Option<Self> {
+        let var = |key: &str| std::env::var(key).ok().filter(|v| !v.trim().is_empty());
+        Some(S3Config {
+            bucket: var("NUE_S3_BUCKET")?,
+            endpoint: var("NUE_S3_ENDPOINT"),
+            region: var("NUE_S3_REGION"),
+            prefix: var("NUE_S3_PREFIX").unwrap_or_default(),
+            public_url: var("NUE_S3_PUBLIC_URL"),
+        })
+    }
+
+    #[cfg(feature = "s3")]
+    fn key_for(&self, path: &Path) -> String {
+        let name = path.file_name().map(|n| n.to_string_lossy().into_owned()).unwrap_or_default();
+        format!("{}{}", self.prefix, name)
+    }
+
+    #[cfg(feature = "s3")]
+    fn url_for(&self, key: &str) -> String {
+        match (&self.public_url, &self.endpoint) {
+            (Some(base), _) => format!("{}/{}", base.trim_end_matches('/'), key),
+            (None, Some(endpoint)) => format!("{}/{}/{}", endpoint.trim_end_matches('/'), self.bucket, key),
+            (None, None) => format!("s3://{}/{}", self.bucket, key),
+        }
+    }
+}
+
+// Upload each file, returning the URLs that succeeded. Failures are logged and skipped;
+// local files are never removed.
+#[cfg(feature = "s3")]
+pub(crate) fn upload_outputs(s3: &S3Config, files: &[PathBuf]) -> Vec<String> {
+    let runtime = match tokio::runtime::Builder::new_current_thread().enable_all().build() {
+        Ok(runtime) => runtime,
+        Err(e) => {
+            log_json("ERROR", &format!("Could not start upload runtime: {}", e), Some("upload_failed"), None);
+            return Vec::new();
+        }
+    };
+
+    runtime.block_on(async {
+        let client = client(s3).await;
+        let mut urls = Vec::new();
+        for file in files {
+            match put(&client, s3, file).await {
+                Ok(url) => {
+                    log_json("INFO", &format!("Uploaded to {}", url), Some("upload_complete"), file.to_str());
+                    urls.push(url);
+                }
+                Err(e) => log_json("ERROR", &format!("Upload failed: {:#}", e), Some("upload_failed"), file.to_str()),
+            }
+        }
+        urls
+    })
+}
+
+#[cfg(not(feature = "s3"))]
+pub(crate) fn upload_outputs(_s3: &S3Config, _files: &[PathBuf]) -> Vec<String> {
+    log_json("WARN", "NUE_S3_BUCKET is set but this build lacks the `s3` feature; skipping upload", Some("upload_skipped"), None);
+    Vec::new()
+}
+
+#[cfg(feature = "s3")]
+async fn client(s3: &S3Config) -> aws_sdk_s3::Client {
+    let mut loader = aws_config::defaults(aws_config::BehaviorVersion::latest());
+    if let Some(region) = &s3.region {
+        loader = loader.region(aws_config::Region::new(region.clone()));
+    }
+    let shared = loader.load().await;
+
+    let mut builder = aws_sdk_s3::config::Builder::from(&shared);
+    if let Some(endpoint) = &s3.endpoint {
+        builder = builder.endpoint_url(endpoint).force_path_style(true);
+    }
+    aws_sdk_s3::Client::from_conf(builder.build())
+}
+
+#[cfg(feature = "s3")]
+async fn put(client: &aws_sdk_s3::Client, s3: &S3Config, file: &Path) -> Result<String> {
+    let key = s3.key_for(file);
+    let body = aws_sdk_s3::primitives::ByteStream::from_path(file).await?;
+    client.put_object()
+        .bucket(&s3.bucket)
+        .key(&key)
+        .body(body)
+        .send()
+        .await?;
+    Ok(s3.url_for(&key))
+}
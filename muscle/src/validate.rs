@@ -0,0 +1,483 @@
+use std::path::{Component, Path};
+
+use crate::analysis::{dimensions_for_aspect, Analysis};
+use crate::ass::CAPTION_RENDERERS;
+use crate::ffmpeg::{OUTPUT_FORMATS, X264_PRESETS};
+use crate::filters::{
+    get_color_filter, is_directional_transition, is_font_path, is_known_transition, is_no_color_filter,
+    parse_caption_offset, CAPTION_ANIMATIONS, CAPTION_POSITIONS, CAPTION_X_PRESETS, FIT_MODES, PREVIEW_FORMATS,
+    PROGRESS_BAR_POSITIONS, THUMBNAIL_BARS, TRANSITION_DIRECTIONS, WATERMARK_POSITIONS, WAVEFORM_POSITIONS,
+    WAVEFORM_STYLES,
+};
+use crate::process::SHORT_CUT_MODES;
+use crate::time::parse_time;
+
+// Semantic checks that serde can't express. Collects every problem rather than
+// stopping at the first, so a rejected file can be fixed in one pass.
+pub fn validate(analysis: &Analysis) -> Result<(), Vec<String>> {
+    let mut problems = Vec::new();
+    let time_format = analysis.time_format;
+
+    if analysis.cuts.is_empty() {
+        problems.push("cuts is empty".to_string());
+    }
+
+    for (i, cut) in analysis.cuts.iter().enumerate() {
+        if let Some(source) = cut.source.as_ref().filter(|source| !is_inside_dir(source)) {
+            problems.push(format!("cut {}: source '{}' must be a relative path inside the raw dir", i, source));
+        }
+
+        // Stills run 0..duration; their start_time/end_time are ignored
+        let (start, end) = if cut.is_still() {
+            match cut.duration {
+                Some(duration) if duration > 0.0 && duration.is_finite() => (Some(0.0), Some(duration)),
+                Some(duration) => {
+                    problems.push(format!("cut {}: invalid duration {}", i, duration));
+                    (None, None)
+                }
+                None => {
+                    problems.push(format!("cut {}: still source needs a duration", i));
+                    (None, None)
+                }
+            }
+        } else {
+            let start = parse_time(&cut.start_time, time_format)
+                .map_err(|e| problems.push(format!("cut {}: bad start_time: {}", i, e)))
+                .ok();
+            let end = parse_time(&cut.end_time, time_format)
+                .map_err(|e| problems.push(format!("cut {}: bad end_time: {}", i, e)))
+                .ok();
+            (start, end)
+        };
+
+        if let (Some(start), Some(end)) = (start, end) {
+            if end < start {
+                problems.push(format!("cut {}: end_time {} is before start_time {}", i, cut.end_time, cut.start_time));
+            } else if end == start {
+                problems.push(format!("cut {}: zero duration at {}", i, cut.start_time));
+            }
+
+            // Karaoke word times are relative to the cut and must not go backwards
+            if let Some(words) = &cut.caption_words {
+                let mut previous = 0.0;
+                for (word, at) in words {
+                    if *at < previous || *at >= end - start {
+                        problems.push(format!("cut {}: caption word '{}' at {} is out of order or past the cut", i, word, at));
+                    }
+                    previous = previous.max(*at);
+                }
+            }
+        }
+
+        if let Some(focus) = cut.focus_point {
+            if !(0.0..=1.0).contains(&focus) {
+                problems.push(format!("cut {}: focus_point {} outside 0..1", i, focus));
+            }
+        }
+
+        if let Some(path) = cut.focus_path {
+            if path.iter().any(|focus| !(0.0..=1.0).contains(focus)) {
+                problems.push(format!("cut {}: focus_path {:?} outside 0..1", i, path));
+            }
+        }
+
+        if let Some(volume) = cut.volume {
+            if volume < 0.0 {
+                problems.push(format!("cut {}: negative volume {}", i, volume));
+            }
+        }
+
+        for (name, freeze) in [("freeze_start", cut.freeze_start), ("freeze_end", cut.freeze_end)] {
+            if let Some(seconds) = freeze {
+                if !(seconds >= 0.0 && seconds.is_finite()) {
+                    problems.push(format!("cut {}: invalid {} {}", i, name, seconds));
+                }
+            }
+        }
+
+        if let Some(lut) = cut.lut.as_ref().filter(|lut| !is_cube_file(lut)) {
+            problems.push(format!("cut {}: lut '{}' is not a .cube file", i, lut));
+        }
+        if let Some(lut) = cut.lut.as_ref().filter(|lut| !is_inside_dir(lut)) {
+            problems.push(format!("cut {}: lut '{}' must be a relative path inside the assets dir", i, lut));
+        }
+
+        if let Some(transition) = &cut.transition_type {
+            if !is_known_transition(transition) {
+                problems.push(format!("cut {}: unknown transition_type '{}'", i, transition));
+            }
+        }
+
+        if let Some(seconds) = cut.transition_duration {
+            if !(seconds > 0.0 && seconds.is_finite()) {
+                problems.push(format!("cut {}: invalid transition_duration {}", i, seconds));
+            }
+        }
+
+        if let Some(direction) = &cut.transition_direction {
+            if !TRANSITION_DIRECTIONS.contains(&direction.as_str()) {
+                problems.push(format!("cut {}: unknown transition_direction '{}' (expected one of {})", i, direction, TRANSITION_DIRECTIONS.join(", ")));
+            } else if !cut.transition_type.as_deref().is_some_and(is_directional_transition) {
+                problems.push(format!("cut {}: transition_direction only applies to wipe/slide transitions", i));
+            }
+        }
+
+        for style in cut.caption_styles() {
+            if let Some(asset) = style.background_asset.as_deref().filter(|asset| !is_inside_dir(asset)) {
+                problems.push(format!("cut {}: background_asset '{}' must be a relative path inside the assets dir", i, asset));
+            }
+            if let Some(position) = style.position.as_deref() {
+                if !CAPTION_POSITIONS.contains(&position) && parse_caption_offset(position, "h").is_none() {
+                    problems.push(format!("cut {}: bad caption position '{}' (expected {}, a percentage or pixels)", i, position, CAPTION_POSITIONS.join(", ")));
+                }
+            }
+            if let Some(x) = style.x.as_deref() {
+                if !CAPTION_X_PRESETS.contains(&x) && parse_caption_offset(x, "w").is_none() {
+                    problems.push(format!("cut {}: bad caption x '{}' (expected {}, a percentage or pixels)", i, x, CAPTION_X_PRESETS.join(", ")));
+                }
+            }
+            if let Some(animation) = style.animation.as_deref() {
+                if !CAPTION_ANIMATIONS.contains(&animation) {
+                    problems.push(format!("cut {}: unknown animation '{}' (expected one of {})", i, animation, CAPTION_ANIMATIONS.join(", ")));
+                }
+            }
+
+            if let Some(width) = style.border_width {
+                if width > 20 {
+                    problems.push(format!("cut {}: border_width {} outside 0..20", i, width));
+                }
+            }
+
+            if let Some(font) = style.font.as_deref() {
+                if is_font_path(font) && !Path::new(font).is_absolute() {
+                    problems.push(format!("cut {}: font path '{}' must be absolute", i, font));
+                }
+            }
+        }
+
+        if get_color_filter(&cut.filter).is_none() && !is_no_color_filter(&cut.filter) {
+            problems.push(format!("cut {}: unknown filter '{}'", i, cut.filter));
+        }
+    }
+
+    if let Some(lufs) = analysis.target_lufs {
+        // loudnorm accepts integrated targets between -70 and -5 LUFS
+        if !(-70.0..=-5.0).contains(&lufs) {
+            problems.push(format!("target_lufs {} outside -70..-5", lufs));
+        }
+    }
+
+    if let Some(crf) = analysis.crf {
+        if crf > 51 {
+            problems.push(format!("crf {} outside 0..51", crf));
+        }
+    }
+
+    if let Some(preset) = &analysis.preset {
+        if !X264_PRESETS.contains(&preset.as_str()) {
+            problems.push(format!("unknown preset '{}' (expected one of {})", preset, X264_PRESETS.join(", ")));
+        }
+    }
+
+    // Container and codec come as a pair; only the combinations the muxers support are listed
+    if let Some(format) = &analysis.output_format {
+        if !OUTPUT_FORMATS.contains(&format.as_str()) {
+            problems.push(format!("unsupported output_format '{}' (expected one of {})", format, OUTPUT_FORMATS.join(", ")));
+        }
+    }
+
+    if let Some(bitrate) = &analysis.audio_bitrate {
+        let digits = bitrate.strip_suffix('k').unwrap_or(bitrate);
+        if !digits.parse::<u32>().is_ok_and(|n| n > 0) {
+            problems.push(format!("audio_bitrate '{}' is not like \"128k\"", bitrate));
+        }
+    }
+
+    if let Some(bitrate) = &analysis.target_bitrate {
+        let digits = bitrate.strip_suffix(['k', 'M']).unwrap_or(bitrate);
+        if !digits.parse::<f64>().is_ok_and(|n| n > 0.0) {
+            problems.push(format!("target_bitrate '{}' is not like \"2500k\" or \"4M\"", bitrate));
+        }
+    }
+
+    if analysis.max_output_bytes == Some(0) {
+        problems.push("max_output_bytes must be positive".to_string());
+    }
+
+    if let Some(boost) = analysis.audio_boost {
+        if !(boost >= 0.0 && boost.is_finite()) {
+            problems.push(format!("invalid audio_boost {}", boost));
+        }
+    }
+
+    if let Some(seconds) = analysis.bgm_fade_in {
+        if !(seconds >= 0.0 && seconds.is_finite()) {
+            problems.push(format!("invalid bgm_fade_in {}", seconds));
+        }
+    }
+
+    if let Some(seconds) = analysis.audio_fade_out {
+        if !(seconds >= 0.0 && seconds.is_finite()) {
+            problems.push(format!("invalid audio_fade_out {}", seconds));
+        }
+    }
+
+    if let Some(lut) = analysis.lut.as_ref().filter(|lut| !is_cube_file(lut)) {
+        problems.push(format!("lut '{}' is not a .cube file", lut));
+    }
+    if let Some(lut) = analysis.lut.as_ref().filter(|lut| !is_inside_dir(lut)) {
+        problems.push(format!("lut '{}' must be a relative path inside the assets dir", lut));
+    }
+
+    if let Some(watermark) = analysis.watermark.as_ref().filter(|watermark| !is_inside_dir(watermark)) {
+        problems.push(format!("watermark '{}' must be a relative path inside the assets dir", watermark));
+    }
+
+    if let Some(position) = &analysis.watermark_position {
+        if !WATERMARK_POSITIONS.contains(&position.as_str()) {
+            problems.push(format!("unknown watermark_position '{}' (expected one of {})", position, WATERMARK_POSITIONS.join(", ")));
+        }
+    }
+
+    if let Some(opacity) = analysis.watermark_opacity {
+        if !(0.0..=1.0).contains(&opacity) {
+            problems.push(format!("watermark_opacity {} outside 0..1", opacity));
+        }
+    }
+
+    if let Some(renderer) = &analysis.caption_renderer {
+        if !CAPTION_RENDERERS.contains(&renderer.as_str()) {
+            problems.push(format!("unknown caption_renderer '{}' (expected one of {})", renderer, CAPTION_RENDERERS.join(", ")));
+        }
+    }
+
+    if let Some(style) = &analysis.waveform {
+        if !WAVEFORM_STYLES.contains(&style.as_str()) {
+            problems.push(format!("unknown waveform '{}' (expected one of {})", style, WAVEFORM_STYLES.join(", ")));
+        }
+    }
+
+    if let Some(position) = &analysis.waveform_position {
+        if !WAVEFORM_POSITIONS.contains(&position.as_str()) {
+            problems.push(format!("unknown waveform_position '{}' (expected one of {})", position, WAVEFORM_POSITIONS.join(", ")));
+        }
+    }
+
+    if let Some(height) = analysis.waveform_height {
+        let (_, frame_height) = analysis.output_dimensions();
+        if height == 0 || height > frame_height {
+            problems.push(format!("waveform_height {} outside 1..{}", height, frame_height));
+        }
+    }
+
+    if let Some(position) = &analysis.progress_bar_position {
+        if !PROGRESS_BAR_POSITIONS.contains(&position.as_str()) {
+            problems.push(format!("unknown progress_bar_position '{}' (expected one of {})", position, PROGRESS_BAR_POSITIONS.join(", ")));
+        }
+    }
+
+    for (i, thumbnail) in analysis.thumbnail_list().into_iter().enumerate() {
+        if let Err(e) = parse_time(&thumbnail.timestamp, time_format) {
+            problems.push(format!("thumbnail {}: bad timestamp: {}", i, e));
+        }
+        if let Some(image) = thumbnail.source_image.as_ref().filter(|image| !is_inside_dir(image)) {
+            problems.push(format!("thumbnail {}: source_image '{}' must be a relative path inside the raw dir", i, image));
+        }
+        if let Some(format) = &thumbnail.preview {
+            if !PREVIEW_FORMATS.contains(&format.as_str()) {
+                problems.push(format!("thumbnail {}: unknown preview '{}' (expected one of {})", i, format, PREVIEW_FORMATS.join(", ")));
+            }
+        }
+        if let Some(font) = thumbnail.font.as_deref() {
+            if is_font_path(font) && !Path::new(font).is_absolute() {
+                problems.push(format!("thumbnail {}: font path '{}' must be absolute", i, font));
+            }
+        }
+        if let Some(bar) = &thumbnail.bar {
+            if !THUMBNAIL_BARS.contains(&bar.as_str()) {
+                problems.push(format!("thumbnail {}: unknown bar '{}' (expected one of {})", i, bar, THUMBNAIL_BARS.join(", ")));
+            }
+        }
+        if let Some(size) = thumbnail.fontsize {
+            if !(8..=400).contains(&size) {
+                problems.push(format!("thumbnail {}: fontsize {} outside 8..400", i, size));
+            }
+        }
+        if let Some(duration) = thumbnail.preview_duration {
+            if !(duration > 0.0 && duration <= 10.0) {
+                problems.push(format!("thumbnail {}: preview_duration {} outside 0..10 seconds", i, duration));
+            }
+        }
+    }
+
+    if let Some(minimum) = analysis.min_cut_duration {
+        if !(0.0..=10.0).contains(&minimum) {
+            problems.push(format!("min_cut_duration {} outside 0..10 seconds", minimum));
+        }
+    }
+    if let Some(threshold) = analysis.silence_threshold {
+        if !(-90.0..=0.0).contains(&threshold) {
+            problems.push(format!("silence_threshold {} outside -90..0 dB", threshold));
+        }
+    }
+    if let Some(duration) = analysis.silence_min_duration {
+        if !(0.1..=10.0).contains(&duration) {
+            problems.push(format!("silence_min_duration {} outside 0.1..10 seconds", duration));
+        }
+    }
+    if let Some(mode) = &analysis.short_cut {
+        if !SHORT_CUT_MODES.contains(&mode.as_str()) {
+            problems.push(format!("unknown short_cut '{}' (expected one of {})", mode, SHORT_CUT_MODES.join(", ")));
+        }
+    }
+
+    if let Some(mode) = &analysis.fit_mode {
+        if !FIT_MODES.contains(&mode.as_str()) {
+            problems.push(format!("unknown fit_mode '{}' (expected one of {})", mode, FIT_MODES.join(", ")));
+        }
+    }
+
+    if let Some(aspect) = &analysis.aspect_ratio {
+        if dimensions_for_aspect(aspect).is_none() {
+            problems.push(format!("unknown aspect_ratio '{}'", aspect));
+        }
+    }
+    match (analysis.width, analysis.height) {
+        (Some(width), Some(height)) => {
+            if width == 0 || height == 0 || width % 2 != 0 || height % 2 != 0 {
+                problems.push(format!("width/height {}x{} must be positive even numbers", width, height));
+            }
+        }
+        (None, None) => {}
+        _ => problems.push("width and height must be given together".to_string()),
+    }
+
+    if let Some(events) = &analysis.se_events {
+        for (i, se) in events.iter().enumerate() {
+            if let Err(e) = parse_time(&se.timestamp, time_format) {
+                problems.push(format!("se_events {}: bad timestamp: {}", i, e));
+            }
+        }
+    }
+
+    if let Some(segments) = &analysis.bgm_segments {
+        for (i, segment) in segments.iter().enumerate() {
+            match (parse_time(&segment.start, time_format), parse_time(&segment.end, time_format)) {
+                (Ok(start), Ok(end)) if end <= start => {
+                    problems.push(format!("bgm_segments {}: end {} is not after start {}", i, segment.end, segment.start));
+                }
+                (Err(e), _) => problems.push(format!("bgm_segments {}: bad start: {}", i, e)),
+                (_, Err(e)) => problems.push(format!("bgm_segments {}: bad end: {}", i, e)),
+                _ => {}
+            }
+            if segment.fade.is_some_and(|fade| !(fade >= 0.0 && fade.is_finite())) {
+                problems.push(format!("bgm_segments {}: invalid fade {:?}", i, segment.fade));
+            }
+        }
+    }
+
+    if let Some(effects) = &analysis.visual_effects {
+        for (i, effect) in effects.iter().enumerate() {
+            match (parse_time(&effect.start, time_format), parse_time(&effect.end, time_format)) {
+                (Ok(start), Ok(end)) if end < start => {
+                    problems.push(format!("visual_effects {}: end {} is before start {}", i, effect.end, effect.start));
+                }
+                (Err(e), _) => problems.push(format!("visual_effects {}: bad start: {}", i, e)),
+                (_, Err(e)) => problems.push(format!("visual_effects {}: bad end: {}", i, e)),
+                _ => {}
+            }
+
+            if let Some(amount) = effect.amount {
+                if !(0.0..=1.0).contains(&amount) {
+                    problems.push(format!("visual_effects {}: amount {} outside 0..1", i, amount));
+                }
+            }
+
+            if matches!(effect.effect_type.as_str(), "slowmo" | "speedup") {
+                if let Some(speed) = &effect.speed {
+                    if !speed.trim().parse::<f64>().is_ok_and(|s| s > 0.0) {
+                        problems.push(format!("visual_effects {}: speed '{}' is not a positive number", i, speed));
+                    }
+                }
+            }
+        }
+    }
+
+    if problems.is_empty() {
+        Ok(())
+    } else {
+        Err(problems)
+    }
+}
+
+// ffmpeg's lut3d picks the parser from the extension; only .cube is accepted here
+fn is_cube_file(path: &str) -> bool {
+    Path::new(path).extension().is_some_and(|ext| ext.eq_ignore_ascii_case("cube"))
+}
+
+// Relative and free of `..`, so joining it onto a directory can't leave that directory
+pub(crate) fn is_inside_dir(path: &str) -> bool {
+    !path.trim().is_empty() && Path::new(path).components().all(|component| matches!(component, Component::Normal(_) | Component::CurDir))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn analysis(cuts: serde_json::Value, extra: serde_json::Value) -> Analysis {
+        let mut json = serde_json::json!({ "original_filename": "in.mp4", "cuts": cuts });
+        json.as_object_mut().unwrap().extend(extra.as_object().unwrap().clone());
+        serde_json::from_value(json).unwrap()
+    }
+
+    fn cut(start: &str, end: &str) -> serde_json::Value {
+        serde_json::json!({ "start_time": start, "end_time": end, "filter": "none" })
+    }
+
+    // The single problem the instruction has; anything else failing would hide what's tested
+    fn problem(analysis: &Analysis) -> String {
+        let problems = validate(analysis).unwrap_err();
+        assert_eq!(problems.len(), 1, "{:?}", problems);
+        problems.into_iter().next().unwrap()
+    }
+
+    #[test]
+    fn accepts_a_valid_instruction() {
+        let mut second = cut("00:00:06", "00:00:09");
+        second["transition_type"] = "fade".into();
+        second["lut"] = "luts/warm.cube".into();
+        let analysis = analysis(serde_json::json!([cut("00:00:01", "00:00:04"), second]), serde_json::json!({
+            "watermark": "./logo.png",
+            "thumbnail": { "timestamp": "00:00:02", "text": "Hi" },
+        }));
+        assert_eq!(validate(&analysis), Ok(()));
+    }
+
+    #[test]
+    fn rejects_a_bad_timestamp() {
+        let analysis = analysis(serde_json::json!([cut("00:1o:05", "00:00:09")]), serde_json::json!({}));
+        assert!(problem(&analysis).starts_with("cut 0: bad start_time:"));
+    }
+
+    #[test]
+    fn rejects_end_before_start() {
+        let analysis = analysis(serde_json::json!([cut("00:00:09", "00:00:04")]), serde_json::json!({}));
+        assert_eq!(problem(&analysis), "cut 0: end_time 00:00:04 is before start_time 00:00:09");
+    }
+
+    #[test]
+    fn rejects_an_unknown_transition() {
+        let mut second = cut("00:00:06", "00:00:09");
+        second["transition_type"] = "teleport".into();
+        let analysis = analysis(serde_json::json!([cut("00:00:01", "00:00:04"), second]), serde_json::json!({}));
+        assert_eq!(problem(&analysis), "cut 1: unknown transition_type 'teleport'");
+    }
+
+    #[test]
+    fn rejects_asset_paths_outside_their_dir() {
+        for path in ["/etc/passwd", "../secret.png", "logos/../../secret.png"] {
+            let analysis = analysis(serde_json::json!([cut("00:00:01", "00:00:04")]), serde_json::json!({ "watermark": path }));
+            assert!(problem(&analysis).contains(path), "{}", path);
+        }
+    }
+}
@@ -0,0 +1,28 @@
+use serde::Serialize;
+use std::path::Path;
+use std::time::Duration;
+
+use crate::logging::log_json;
+
+const WEBHOOK_TIMEOUT: Duration = Duration::from_secs(5);
+
+#[derive(Serialize)]
+pub(crate) struct WebhookPayload<'a> {
+    pub success: bool,
+    // Up to date already; output points at the existing render
+    pub skipped: bool,
+    pub original_filename: &'a str,
+    pub output: Option<&'a Path>,
+    pub duration: f64,
+    pub error: Option<&'a str>,
+}
+
+// POST the render result. Best-effort: a slow or dead endpoint costs at most the timeout
+// and only produces a WARN.
+pub(crate) fn send_webhook(url: &str, payload: &WebhookPayload) {
+    let agent = ureq::AgentBuilder::new().timeout(WEBHOOK_TIMEOUT).build();
+    match agent.post(url).send_json(payload) {
+        Ok(response) => log_json("INFO", &format!("Webhook delivered ({})", response.status()), Some("webhook_sent"), Some(url)),
+        Err(e) => log_json("WARN", &format!("Webhook failed: {}", e), Some("webhook_failed"), Some(url)),
+    }
+}